@@ -305,6 +305,7 @@ criterion_group!(
 );
 criterion_main!(benches);
 
+#[allow(clippy::too_many_arguments)]
 fn run<const RANGE: usize>(
     c: &mut Criterion,
     name: &str,
@@ -352,7 +353,7 @@ pub(crate) fn random_ops(
     len: usize,
 ) -> VecDeque<FakeMapOp> {
     let rng = rand::thread_rng();
-    let dist = Uniform::new(0, usize::MAX);
+    let dist = Uniform::new(0, std::usize::MAX);
     rng.sample_iter(&dist)
         .take(len)
         .map(|x| {
@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use left_right::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/* # What this measures:
+ * publish_idle_readers: the cost of `publish` (and so of `wait`) with this many registered, but idle, readers.
+ * enter_busy_neighbors: the cost of `enter` on one handle, while this many threads enter and exit through their own handles,
+ *  which were registered right next to it. Slots that share cache lines make this grow with the number of threads.
+ */
+
+struct CounterAddOp(i32);
+
+impl Absorb<CounterAddOp> for i32 {
+    fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+        *self += operation.0;
+    }
+
+    fn sync_with(&mut self, first: &Self) {
+        *self = *first
+    }
+}
+
+fn publish_idle_readers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish_idle_readers");
+    for readers in [1, 100, 1000, 5000] {
+        let (mut w, r) = new::<i32, CounterAddOp>();
+        let _handles: Vec<_> = (0..readers).map(|_| r.clone()).collect();
+        group.bench_with_input(BenchmarkId::from_parameter(readers), &readers, |b, _| {
+            b.iter(|| {
+                w.append(CounterAddOp(1));
+                w.publish();
+            })
+        });
+    }
+    group.finish();
+}
+
+// the benchmarks are not held to the MSRV of the crate itself.
+#[allow(clippy::incompatible_msrv)]
+fn enter_busy_neighbors(c: &mut Criterion) {
+    let mut group = c.benchmark_group("enter_busy_neighbors");
+    let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+    for threads in [0, 1, 3, 7]
+        .iter()
+        .copied()
+        .filter(|&n| n < parallelism.max(2))
+    {
+        let (_w, r) = new::<i32, CounterAddOp>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let neighbors: Vec<_> = (0..threads)
+            .map(|_| {
+                let r = r.clone();
+                let stop = Arc::clone(&stop);
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        black_box(*r.enter().unwrap());
+                    }
+                })
+            })
+            .collect();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, _| {
+            b.iter(|| black_box(*r.enter().unwrap()))
+        });
+        stop.store(true, Ordering::Relaxed);
+        for neighbor in neighbors {
+            neighbor.join().unwrap();
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, publish_idle_readers, enter_busy_neighbors);
+criterion_main!(benches);
@@ -0,0 +1,5 @@
+fn main() {
+    // the loom models are run with `RUSTFLAGS="--cfg loom"`, see the crate docs.
+    println!("cargo:rustc-check-cfg=cfg(loom)");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,267 @@
+use crate::read::ReaderInfo;
+use crate::sync::{Arc, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::ptr::{self, NonNull};
+
+/// The number of slots in the first segment of an [`EpochRegistry`].
+///
+/// Every segment after that is as large as all the ones before it combined, so the number of
+/// segments only grows logarithmically with the number of readers.
+///
+/// Under loom, every slot the writer scans is another interleaving to explore, so keep segments
+/// tiny there. That way the models also cover registrations that have to append a segment.
+const FIRST_SEGMENT_LEN: usize = if cfg!(loom) { 2 } else { 32 };
+
+/// The epoch counters of all the readers of a left-right instance.
+///
+/// Readers register and deregister without ever waiting for one another, or for the writer: the
+/// registry is an append-only list of segments of slots, and readers claim and release slots with
+/// a single atomic operation. Segments are only freed along with the registry itself, so the
+/// writer can scan the counters while readers come and go.
+///
+/// The slots in each segment are laid out contiguously, so the writer can scan them without
+/// chasing a pointer per reader, and each slot has a cache line to itself, so readers that
+/// enter and exit all the time do not slow down their neighbors.
+///
+/// A slot's counter is never reset, so it keeps counting up across readers that reuse the slot.
+/// This way, the writer never mistakes a new reader for one it was already waiting for.
+pub(crate) struct EpochRegistry {
+    head: Segment,
+}
+
+struct Segment {
+    /// The index of the first slot in this segment.
+    start: usize,
+    slots: Box<[Slot]>,
+    next: AtomicPtr<Segment>,
+}
+
+// 128 rather than 64 bytes, since some CPUs (like recent x86 ones) prefetch cache lines in pairs.
+#[repr(align(128))]
+struct Slot {
+    epoch: AtomicUsize,
+    claimed: AtomicBool,
+    /// The number of times the current reader has entered. Only ever written by that reader.
+    enters: AtomicUsize,
+}
+
+impl Segment {
+    fn new(start: usize, len: usize) -> Self {
+        Self {
+            start,
+            slots: (0..len)
+                .map(|_| Slot {
+                    epoch: AtomicUsize::new(0),
+                    claimed: AtomicBool::new(false),
+                    enters: AtomicUsize::new(0),
+                })
+                .collect(),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn next(&self) -> Option<&Segment> {
+        // safety: segments are only freed when the registry is dropped, and we borrow it.
+        unsafe { self.next.load(Ordering::Acquire).as_ref() }
+    }
+}
+
+impl Default for EpochRegistry {
+    fn default() -> Self {
+        Self {
+            head: Segment::new(0, FIRST_SEGMENT_LEN),
+        }
+    }
+}
+
+impl std::fmt::Debug for EpochRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Drop for EpochRegistry {
+    fn drop(&mut self) {
+        let mut next = self.head.next.load(Ordering::Acquire);
+        while !next.is_null() {
+            // safety: every segment after the head was created from a `Box`, and we have
+            // exclusive access to all of them.
+            let segment = unsafe { Box::from_raw(next) };
+            next = segment.next.load(Ordering::Acquire);
+        }
+    }
+}
+
+impl EpochRegistry {
+    fn segments(&self) -> impl Iterator<Item = &Segment> {
+        std::iter::successors(Some(&self.head), |segment| segment.next())
+    }
+
+    /// Claim a slot for a new reader, appending a segment if all slots are taken.
+    pub(crate) fn register(registry: &Arc<Self>) -> Registration {
+        let mut segment = &registry.head;
+        loop {
+            for (i, slot) in segment.slots.iter().enumerate() {
+                if !slot.claimed.load(Ordering::Relaxed)
+                    && slot
+                        .claimed
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    slot.enters.store(0, Ordering::Relaxed);
+                    return Registration {
+                        registry: Arc::clone(registry),
+                        slot: NonNull::from(slot),
+                        index: segment.start + i,
+                    };
+                }
+            }
+
+            segment = match segment.next() {
+                Some(next) => next,
+                None => {
+                    let start = segment.start + segment.slots.len();
+                    let new = Box::into_raw(Box::new(Segment::new(start, start)));
+                    match segment.next.compare_exchange(
+                        ptr::null_mut(),
+                        new,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        // safety: we just created it, and it is freed along with the registry.
+                        Ok(_) => unsafe { &*new },
+                        Err(winner) => {
+                            // another reader got there first, so use theirs.
+                            // safety: the new segment was never shared.
+                            drop(unsafe { Box::from_raw(new) });
+                            // safety: as for `Segment::next`.
+                            unsafe { &*winner }
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    /// Returns the epoch counters of all slots, claimed or not, in index order.
+    ///
+    /// Unclaimed slots always have even epochs, since readers only release their slot once they
+    /// have left the data. Slots appended while iterating may or may not be included.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &AtomicUsize> {
+        self.segments()
+            .flat_map(|segment| segment.slots.iter())
+            .map(|slot| &slot.epoch)
+    }
+
+    /// Returns the number of claimed slots, that is, the number of registered readers.
+    pub(crate) fn registered(&self) -> usize {
+        self.segments()
+            .flat_map(|segment| segment.slots.iter())
+            .filter(|slot| slot.claimed.load(Ordering::Acquire))
+            .count()
+    }
+
+    /// Returns what the claimed slots say about their readers.
+    pub(crate) fn readers(&self) -> impl Iterator<Item = ReaderInfo> + '_ {
+        self.segments()
+            .flat_map(|segment| (segment.start..).zip(segment.slots.iter()))
+            .filter(|(_, slot)| slot.claimed.load(Ordering::Acquire))
+            .map(|(index, slot)| ReaderInfo {
+                index,
+                active: slot.epoch.load(Ordering::Acquire) % 2 != 0,
+                enters: slot.enters.load(Ordering::Relaxed),
+            })
+    }
+}
+
+/// A slot in an [`EpochRegistry`] claimed by a single reader, and released again when dropped.
+pub(crate) struct Registration {
+    registry: Arc<EpochRegistry>,
+    slot: NonNull<Slot>,
+    index: usize,
+}
+
+// safety: a `Registration` is just a reference into the registry it holds on to, and a `Slot` is
+// all atomics.
+unsafe impl Send for Registration {}
+unsafe impl Sync for Registration {}
+
+impl Registration {
+    fn slot(&self) -> &Slot {
+        // safety: the slot is freed along with the registry, and we hold on to that.
+        unsafe { self.slot.as_ref() }
+    }
+
+    /// This reader's epoch counter.
+    pub(crate) fn epoch(&self) -> &AtomicUsize {
+        &self.slot().epoch
+    }
+
+    /// Count another enter by this reader.
+    pub(crate) fn count_enter(&self) {
+        let enters = &self.slot().enters;
+        // we're the only ones writing to this, so there's no need for a (costlier) `fetch_add`.
+        enters.store(
+            enters.load(Ordering::Relaxed).wrapping_add(1),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// The number of times this reader has entered.
+    pub(crate) fn enters(&self) -> usize {
+        self.slot().enters.load(Ordering::Relaxed)
+    }
+
+    /// The index of this reader's slot, which stays the same for as long as it is registered.
+    #[cfg(test)]
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn registry(&self) -> &Arc<EpochRegistry> {
+        &self.registry
+    }
+}
+
+impl std::fmt::Debug for Registration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registration")
+            .field("index", &self.index)
+            .field("epoch", self.epoch())
+            .finish()
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // the counter stays as it is, so the next reader in this slot picks up where we left off.
+        self.slot().claimed.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_grow_and_are_reused() {
+        let registry: crate::Epochs = Default::default();
+        let mut registrations: Vec<_> = (0..1000)
+            .map(|_| EpochRegistry::register(&registry))
+            .collect();
+        for (i, registration) in registrations.iter().enumerate() {
+            assert_eq!(registration.index(), i);
+        }
+        // 32 + 32 + 64 + 128 + 256 + 512
+        assert_eq!(registry.segments().count(), 6);
+        assert_eq!(registry.iter().count(), 1024);
+        assert_eq!(registry.registered(), 1000);
+
+        // a released slot keeps counting where its last reader left off
+        registrations[40].epoch().fetch_add(2, Ordering::AcqRel);
+        registrations.swap_remove(40);
+        let reused = EpochRegistry::register(&registry);
+        assert_eq!(reused.index(), 40);
+        assert_eq!(reused.epoch().load(Ordering::Acquire), 2);
+        assert_eq!(registry.registered(), 1000);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{Absorb, ReadHandle, WriteHandle};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Deref;
+// These are deliberately the std primitives rather than the ones in `crate::sync`: the feed is
+// fed by a publish hook, and plays no part in the synchronization between readers and the writer.
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+/// A feed of the operations that went into each published version of a left-right instance.
+///
+/// Created by [`WriteHandle::change_feed`]. Readers that maintain something derived from the data
+/// can use this to find out what changed since they last looked, instead of comparing the entire
+/// data structure.
+///
+/// A `ChangeFeed` only keeps a bounded number of publishes around. Readers that fall further behind
+/// than that get a [`HistoryTruncated`] error, and have to start over from the current state.
+///
+/// Every reader can get at the most recently created feed through [`ReadHandle::changes_since`].
+/// The feed itself is a separate handle as well, which is cheap to clone, and can be shared across
+/// threads.
+pub struct ChangeFeed<O> {
+    history: Arc<Mutex<History<O>>>,
+}
+
+struct History<O> {
+    /// The generation the feed was created at. Changes from before then were never recorded.
+    start: usize,
+    capacity: usize,
+    batches: VecDeque<Changes<O>>,
+}
+
+/// The operations that produced a single published version of a left-right instance.
+///
+/// Dereferences to the (immutable, shared) slice of operations.
+pub struct Changes<O> {
+    generation: usize,
+    ops: Arc<[O]>,
+}
+
+/// The error returned by [`ChangeFeed::changes_since`] when the feed no longer has all the
+/// changes that were asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HistoryTruncated {
+    oldest: usize,
+}
+
+impl HistoryTruncated {
+    /// The oldest generation the feed can still give all changes since.
+    pub fn oldest_available(&self) -> usize {
+        self.oldest
+    }
+}
+
+impl fmt::Display for HistoryTruncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the change history only goes back to generation {}",
+            self.oldest
+        )
+    }
+}
+
+impl std::error::Error for HistoryTruncated {}
+
+impl<O> Changes<O> {
+    /// The [generation](crate::ReadHandle::generation) these operations produced.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Returns the operations as a shared slice.
+    pub fn ops(&self) -> &Arc<[O]> {
+        &self.ops
+    }
+}
+
+impl<O> Clone for Changes<O> {
+    fn clone(&self) -> Self {
+        Self {
+            generation: self.generation,
+            ops: Arc::clone(&self.ops),
+        }
+    }
+}
+
+impl<O: fmt::Debug> fmt::Debug for Changes<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Changes")
+            .field("generation", &self.generation)
+            .field("ops", &&*self.ops)
+            .finish()
+    }
+}
+
+impl<O> Deref for Changes<O> {
+    type Target = [O];
+    fn deref(&self) -> &Self::Target {
+        &self.ops
+    }
+}
+
+impl<O> Clone for ChangeFeed<O> {
+    fn clone(&self) -> Self {
+        Self {
+            history: Arc::clone(&self.history),
+        }
+    }
+}
+
+impl<O> fmt::Debug for ChangeFeed<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let history = self.history();
+        f.debug_struct("ChangeFeed")
+            .field("start", &history.start)
+            .field("capacity", &history.capacity)
+            .field("len", &history.batches.len())
+            .finish()
+    }
+}
+
+impl<O> ChangeFeed<O> {
+    fn history(&self) -> MutexGuard<'_, History<O>> {
+        // the lock is never held across anything that can panic half-way through an update.
+        self.history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the changes of every publish after `generation`, oldest first.
+    ///
+    /// To keep up with a left-right instance, pass in the [generation](Changes::generation) of
+    /// the last `Changes` you have seen. Note that readers may already observe a new generation
+    /// through their [`ReadHandle`] a moment before its changes show up here.
+    ///
+    /// Returns an error if changes after `generation` have already been discarded, or were
+    /// published before the feed was created.
+    pub fn changes_since(&self, generation: usize) -> Result<Vec<Changes<O>>, HistoryTruncated> {
+        let history = self.history();
+        let oldest = history
+            .batches
+            .front()
+            .map_or(history.start, |changes| changes.generation - 1);
+        if generation < oldest {
+            return Err(HistoryTruncated { oldest });
+        }
+
+        Ok(history
+            .batches
+            .iter()
+            .skip_while(|changes| changes.generation <= generation)
+            .cloned()
+            .collect())
+    }
+
+    /// Returns the changes of the most recent publish the feed knows about, if any.
+    pub fn latest(&self) -> Option<Changes<O>> {
+        self.history().batches.back().cloned()
+    }
+}
+
+impl<T, O> WriteHandle<T, O>
+where
+    T: Absorb<O>,
+    O: Clone + Send + Sync + 'static,
+{
+    /// Start recording the operations of every publish, and return a [`ChangeFeed`] that gives
+    /// access to the last `history` of them.
+    ///
+    /// The operations are recorded by a [publish hook](Self::on_publish), which clones them into a
+    /// shared slice. The feed starts out empty, with changes since the current generation.
+    ///
+    /// The left-right instance keeps the most recently created feed around, for
+    /// [`ReadHandle::changes_since`]. Any feed before it stops recording, and its hook is dropped,
+    /// once it and all its clones are gone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `history` is zero, and in the same cases as [`on_publish`](Self::on_publish).
+    pub fn change_feed(&mut self, history: usize) -> ChangeFeed<O> {
+        assert!(history > 0, "a change feed must keep at least one publish");
+
+        let feed = ChangeFeed {
+            history: Arc::new(Mutex::new(History {
+                start: 0,
+                capacity: history,
+                batches: VecDeque::with_capacity(history),
+            })),
+        };
+        // the hook must not keep the history alive once nobody can read it anymore.
+        let recorder = Arc::downgrade(&feed.history);
+        self.on_publish_while(Box::new(move |batch| {
+            let feed = match Weak::upgrade(&recorder) {
+                Some(history) => ChangeFeed { history },
+                None => return false,
+            };
+            let changes = Changes {
+                generation: batch.generation(),
+                ops: batch.iter().cloned().collect(),
+            };
+            let mut history = feed.history();
+            if history.batches.len() == history.capacity {
+                history.batches.pop_front();
+            }
+            history.batches.push_back(changes);
+            true
+        }));
+        // the hook does not see anything until the next publish.
+        feed.history().start = self.generation();
+
+        let history = Arc::clone(&feed.history);
+        *self.inner.change_feed.lock().unwrap() = Some(history as Arc<dyn Any + Send + Sync>);
+        feed
+    }
+}
+
+impl<T> ReadHandle<T> {
+    /// Returns the changes of every publish after `generation`, oldest first.
+    ///
+    /// This reads from the most recent [`ChangeFeed`] the writer created with
+    /// [`WriteHandle::change_feed`], just like [`ChangeFeed::changes_since`] does. `O` has to be
+    /// the operation type of the writer.
+    ///
+    /// Returns an error if changes after `generation` have already been discarded, or were
+    /// published before the feed was created. If the writer has not created a feed for operations
+    /// of type `O`, none were recorded, so the history only goes back to the current generation.
+    pub fn changes_since<O>(&self, generation: usize) -> Result<Vec<Changes<O>>, HistoryTruncated>
+    where
+        O: Send + Sync + 'static,
+    {
+        let history = self.inner.change_feed.lock().unwrap().clone();
+        match history.and_then(|history| history.downcast::<Mutex<History<O>>>().ok()) {
+            Some(history) => ChangeFeed { history }.changes_since(generation),
+            None => Err(HistoryTruncated {
+                oldest: self.generation(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Absorb;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Add(i32);
+
+    impl Absorb<Add> for i32 {
+        fn absorb_first(&mut self, operation: &mut Add, _: &Self) {
+            *self += operation.0;
+        }
+
+        fn sync_with(&mut self, first: &Self) {
+            *self = *first
+        }
+    }
+
+    fn ops(changes: &[super::Changes<Add>]) -> Vec<(usize, Vec<i32>)> {
+        changes
+            .iter()
+            .map(|c| (c.generation(), c.iter().map(|op| op.0).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn changes_since() {
+        let (mut w, r) = crate::new::<i32, Add>();
+        w.append(Add(1)).publish();
+        let feed = w.change_feed(2);
+        let start = r.generation();
+        assert_eq!(start, 1);
+        assert!(feed.changes_since(start).unwrap().is_empty());
+        assert!(feed.latest().is_none());
+        assert_eq!(feed.changes_since(0).unwrap_err().oldest_available(), 1);
+
+        w.append(Add(2));
+        w.append(Add(3));
+        w.publish();
+        assert_eq!(ops(&feed.changes_since(start).unwrap()), [(2, vec![2, 3])]);
+
+        w.publish();
+        w.append(Add(4));
+        w.publish();
+        assert_eq!(
+            ops(&feed.changes_since(2).unwrap()),
+            [(3, vec![]), (4, vec![4])]
+        );
+        assert_eq!(feed.latest().unwrap().generation(), r.generation());
+        // generation 2 has been pushed out
+        assert_eq!(
+            feed.changes_since(start).unwrap_err(),
+            super::HistoryTruncated { oldest: 2 }
+        );
+        assert!(feed.changes_since(4).unwrap().is_empty());
+
+        // clones share the history, and slices are shared rather than copied
+        let clone = feed.clone();
+        let a = feed.latest().unwrap();
+        let b = clone.latest().unwrap();
+        assert!(std::sync::Arc::ptr_eq(a.ops(), b.ops()));
+    }
+
+    #[test]
+    fn changes_since_through_read_handle() {
+        let (mut w, r) = crate::new::<i32, Add>();
+        w.publish();
+        // nothing is recorded without a feed
+        assert_eq!(r.changes_since::<Add>(1).unwrap_err().oldest_available(), 1);
+
+        let feed = w.change_feed(2);
+        w.append(Add(1)).publish();
+        assert_eq!(ops(&r.changes_since::<Add>(1).unwrap()), [(2, vec![1])]);
+        assert_eq!(
+            r.changes_since::<Add>(0).unwrap_err(),
+            feed.changes_since(0).unwrap_err()
+        );
+        // the feed has to be for the right operation type
+        assert_eq!(r.changes_since::<i32>(1).unwrap_err().oldest_available(), 2);
+
+        // the instance keeps the feed around for readers
+        drop(feed);
+        w.append(Add(2)).publish();
+        assert_eq!(
+            ops(&r.changes_since::<Add>(1).unwrap()),
+            [(2, vec![1]), (3, vec![2])]
+        );
+    }
+
+    #[test]
+    fn replaced_feed_is_released() {
+        let (mut w, r) = crate::new::<i32, Add>();
+        w.publish();
+        let feed = w.change_feed(2);
+        w.append(Add(1)).publish();
+        assert_eq!(ops(&feed.changes_since(1).unwrap()), [(2, vec![1])]);
+
+        let history = std::sync::Arc::downgrade(&feed.history);
+        let clone = feed.clone();
+        drop(feed);
+        let newer = w.change_feed(2);
+        w.append(Add(2)).publish();
+        assert_eq!(clone.latest().unwrap().generation(), 3);
+        assert_eq!(ops(&r.changes_since::<Add>(2).unwrap()), [(3, vec![2])]);
+
+        // the hook does not keep the history around once the last feed is gone
+        drop(clone);
+        assert!(history.upgrade().is_none());
+        w.append(Add(3)).publish();
+        assert_eq!(*w.enter().unwrap(), 6);
+        assert_eq!(newer.latest().unwrap().generation(), 4);
+    }
+}
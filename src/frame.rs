@@ -0,0 +1,214 @@
+//! The on-the-wire (and on-disk) format of a batch of operations.
+//!
+//! Every frame starts with a fixed-size header, followed by the payload:
+//!
+//! ```text
+//! +-------------+-------------+-----------------+----------------+--------------------------+
+//! | len: u32 LE | crc: u32 LE | generation: u64 | high-water mark | payload: count: u32, then |
+//! |             |             |       LE        |    : u64 LE    | count times (len: u32 LE, |
+//! |             |             |                 |                |        op bytes)          |
+//! +-------------+-------------+-----------------+----------------+--------------------------+
+//! ```
+//!
+//! `len` is the length of the payload, and `crc` is the CRC32 of everything after it. The
+//! high-water mark is that of [`WriteHandle::append_seq`](crate::WriteHandle::append_seq) plus
+//! one, or zero if there is none.
+//! The operations themselves are encoded by the user, so they are opaque byte strings here. Frames
+//! that hold something other than operations (like a checkpoint) have a payload of their own.
+
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+/// Turns operations into bytes, and back again.
+///
+/// Used both to [persist](crate::persist) operations and to
+/// [replicate](crate::replication) them.
+pub trait OpCodec<O> {
+    /// Append the encoding of `operation` to `buf`.
+    ///
+    /// Must not touch what is in `buf` already.
+    fn encode(&self, operation: &O, buf: &mut Vec<u8>);
+
+    /// Decode an operation from exactly the bytes [`encode`](Self::encode) produced for it.
+    fn decode(&self, bytes: &[u8]) -> io::Result<O>;
+}
+
+/// The length of a frame header: the payload length, the checksum, the generation, and the
+/// high-water mark.
+pub(crate) const HEADER_LEN: usize = 4 + 4 + 8 + 8;
+
+/// A frame whose checksum has been verified, but whose operations have not been decoded yet.
+#[derive(Debug)]
+pub(crate) struct Frame {
+    pub(crate) generation: u64,
+    pub(crate) high_water_mark: Option<u64>,
+    pub(crate) payload: Vec<u8>,
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    let bytes = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut n = [0; 8];
+    n.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(n)
+}
+
+/// Start a new frame at the end of `out`, and return where it starts.
+///
+/// The payload should be appended to `out` next, after which [`seal`] completes the frame.
+pub(crate) fn begin(out: &mut Vec<u8>) -> usize {
+    let start = out.len();
+    out.resize(start + HEADER_LEN, 0);
+    start
+}
+
+/// Fill in the header of the frame that [`begin`] started at `start`, now that everything after
+/// that in `out` is its payload.
+pub(crate) fn seal(out: &mut [u8], start: usize, generation: u64, high_water_mark: Option<u64>) {
+    let payload = start + HEADER_LEN;
+    let len = u32::try_from(out.len() - payload).expect("frame is too large");
+    // `append_seq` does not accept u64::MAX, so this does not overflow.
+    let high_water_mark = high_water_mark.map_or(0, |seq| seq + 1);
+    out[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    out[start + 8..start + 16].copy_from_slice(&generation.to_le_bytes());
+    out[start + 16..payload].copy_from_slice(&high_water_mark.to_le_bytes());
+    let crc = crc32fast::hash(&out[start + 8..]);
+    out[start + 4..start + 8].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Append a frame with the given `ops` to `out`, using `encode` to turn each one into bytes.
+pub(crate) fn encode<'a, O, I, F>(
+    generation: u64,
+    high_water_mark: Option<u64>,
+    ops: I,
+    mut encode: F,
+    out: &mut Vec<u8>,
+) where
+    O: 'a,
+    I: IntoIterator<Item = &'a O>,
+    F: FnMut(&O, &mut Vec<u8>),
+{
+    let start = begin(out);
+    let count_at = out.len();
+    out.extend_from_slice(&[0; 4]);
+    let mut count = 0u32;
+    for op in ops {
+        let at = out.len();
+        out.extend_from_slice(&[0; 4]);
+        encode(op, out);
+        let len = u32::try_from(out.len() - at - 4).expect("encoded operation is too large");
+        out[at..at + 4].copy_from_slice(&len.to_le_bytes());
+        count += 1;
+    }
+    out[count_at..count_at + 4].copy_from_slice(&count.to_le_bytes());
+    seal(out, start, generation, high_water_mark);
+}
+
+/// Read the next frame from `reader`.
+///
+/// Returns `Ok(None)` if `reader` ends right where the next frame would start. If it ends part-way
+/// through a frame, this returns an [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error, and if
+/// the frame is corrupt, an [`InvalidData`](io::ErrorKind::InvalidData) error.
+pub(crate) fn read<R: Read>(reader: &mut R) -> io::Result<Option<Frame>> {
+    let mut header = [0; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let len = u32_at(&header, 0).unwrap() as usize;
+    let crc = u32_at(&header, 4).unwrap();
+
+    // a corrupt length should not make us allocate gigabytes up front, so read incrementally.
+    let mut payload = Vec::new();
+    reader.take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[8..]);
+    hasher.update(&payload);
+    if hasher.finalize() != crc {
+        return Err(invalid("frame checksum mismatch"));
+    }
+
+    Ok(Some(Frame {
+        generation: u64_at(&header, 8),
+        high_water_mark: u64_at(&header, 16).checked_sub(1),
+        payload,
+    }))
+}
+
+impl Frame {
+    /// The number of bytes this frame takes up when encoded.
+    #[cfg(feature = "persist")]
+    pub(crate) fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+
+    /// Decode the operations in this frame, using `decode` to turn the bytes of each one back
+    /// into an operation.
+    pub(crate) fn ops<O, F>(&self, mut decode: F) -> io::Result<Vec<O>>
+    where
+        F: FnMut(&[u8]) -> io::Result<O>,
+    {
+        let bytes = &self.payload[..];
+        let count = u32_at(bytes, 0).ok_or_else(|| invalid("frame has no operation count"))?;
+        let mut at = 4;
+        let mut ops = Vec::with_capacity(count.min(1024) as usize);
+        for _ in 0..count {
+            let len = u32_at(bytes, at).ok_or_else(|| invalid("truncated operation in frame"))?;
+            at += 4;
+            let op = bytes
+                .get(at..at + len as usize)
+                .ok_or_else(|| invalid("truncated operation in frame"))?;
+            ops.push(decode(op)?);
+            at += len as usize;
+        }
+        if at != bytes.len() {
+            return Err(invalid("trailing bytes in frame"));
+        }
+        Ok(ops)
+    }
+}
+
+/// Encodes operations that carry a single `i32`, for the tests of the modules that send them
+/// through frames.
+///
+/// The operation types themselves come from `utilities.rs`, which every test module includes
+/// separately, so this cannot name them.
+#[cfg(test)]
+pub(crate) struct I32Codec;
+
+#[cfg(test)]
+impl<O> OpCodec<O> for I32Codec
+where
+    O: From<i32>,
+    i32: for<'a> From<&'a O>,
+{
+    fn encode(&self, operation: &O, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&i32::from(operation).to_le_bytes());
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<O> {
+        let mut n = [0; 4];
+        if bytes.len() != n.len() {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        n.copy_from_slice(bytes);
+        Ok(O::from(i32::from_le_bytes(n)))
+    }
+}
@@ -22,19 +22,21 @@
 //! primitive are:
 //!
 //!  - **Increased memory use**: since we keep two copies of the backing data structure, we are
-//!  effectively doubling the memory use of the underlying data. With some clever de-duplication,
-//!  this cost can be ameliorated to some degree, but it's something to be aware of. Furthermore,
-//!  if writers only call `publish` infrequently despite adding many writes to the operational log,
-//!  the operational log itself may grow quite large, which adds additional overhead.
+//!    effectively doubling the memory use of the underlying data. With some clever
+//!    de-duplication, this cost can be ameliorated to some degree, but it's something to be aware
+//!    of. Furthermore, if writers only call `publish` infrequently despite adding many writes to
+//!    the operational log, the operational log itself may grow quite large, which adds additional
+//!    overhead.
 //!  - **Deterministic operations**: as the entries in the operational log are applied twice, once
-//!  to each copy of the data, it is essential that the operations are deterministic. If they are
-//!  not, the two copies will no longer mirror one another, and will continue to diverge over time.
+//!    to each copy of the data, it is essential that the operations are deterministic. If they are
+//!    not, the two copies will no longer mirror one another, and will continue to diverge over
+//!    time.
 //!  - **Single writer**: left-right only supports a single writer. To have multiple writers, you
-//!  need to ensure exclusive access to the [`WriteHandle`] through something like a
-//!  [`Mutex`](std::sync::Mutex).
+//!    need to ensure exclusive access to the [`WriteHandle`] through something like a
+//!    [`Mutex`](std::sync::Mutex).
 //!  - **Slow writes**: Writes through left-right are slower than they would be directly against
-//!  the backing datastructure. This is both because they have to go through the operational log,
-//!  and because they must each be applied twice.
+//!    the backing datastructure. This is both because they have to go through the operational log,
+//!    and because they must each be applied twice.
 //!
 //! # How does it work?
 //!
@@ -166,6 +168,27 @@
 //! closure instead. Instead, consider using [`ReadGuard::map`] and [`ReadGuard::try_map`], which
 //! (like `RefCell`'s [`Ref::map`](std::cell::Ref::map)) allow you to provide a guarded reference
 //! deeper into your data structure.
+//!
+//! # Model checking with loom
+//!
+//! Left-right is tested with [loom](https://docs.rs/loom), which runs a concurrent test under
+//! every possible interleaving of its threads. If you build a wrapper around left-right, you can
+//! do the same for your own use of it. Compiling with `--cfg loom` builds left-right against
+//! loom's synchronization primitives, and re-exports the `loom` crate it uses, so that your
+//! models are guaranteed to use the same version:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+//! ```
+//!
+//! This is a `cfg` rather than a cargo feature on purpose. Loom's primitives can only be used
+//! inside [`loom::model`](https://docs.rs/loom/0.4/loom/fn.model.html), so with it set, _every_
+//! use of left-right has to happen inside a model. Features are additive, and one enabled by any
+//! crate in the dependency graph would break every other user of left-right in the same build.
+//! Run the models in release mode, since loom explores a lot of interleavings. Also keep in mind
+//! that loom cannot model `SeqCst` fences yet, which left-right relies on to tell when readers
+//! have left a copy. Models with more than one [`WriteHandle::publish`] that overlaps with reads
+//! may therefore report false positives.
 #![warn(
     missing_docs,
     rust_2018_idioms,
@@ -174,18 +197,41 @@
 )]
 #![allow(clippy::type_complexity)]
 
+mod epochs;
+mod feed;
+#[cfg(any(feature = "persist", feature = "replication"))]
+mod frame;
+mod shared;
 mod sync;
 
-use crate::sync::{Arc, AtomicUsize, Mutex};
+use crate::sync::Arc;
 
-type Epochs = Arc<Mutex<slab::Slab<Arc<AtomicUsize>>>>;
+type Epochs = Arc<epochs::EpochRegistry>;
 
 mod write;
-pub use crate::write::Taken;
+pub use crate::feed::{ChangeFeed, Changes, HistoryTruncated};
 pub use crate::write::WriteHandle;
+pub use crate::write::{Batch, Divergence, PublishHookId, Taken};
+
+#[cfg(feature = "persist")]
+pub mod persist;
+#[cfg(feature = "persist")]
+pub use crate::persist::recover;
+#[cfg(feature = "replication")]
+pub mod replication;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+/// The version of loom left-right is built against under `--cfg loom`.
+#[cfg(loom)]
+pub use loom;
 
 mod read;
-pub use crate::read::{ReadGuard, ReadHandle, ReadHandleFactory};
+pub use crate::read::{
+    DynReadHandle, EnterError, MappedReadHandle, MappedReadHandleFactory, OwnedReadGuard,
+    ReadGuard, ReadHandle, ReadHandleFactory, ReaderInfo, Snapshot, SyncReadHandle, VersionGone,
+    ZippedReadGuard,
+};
 
 pub mod aliasing;
 
@@ -283,6 +329,11 @@ pub trait Absorb<O> {
     /// often assumed to be deterministic (like `Eq` and `Hash`), and of "hidden states" that
     /// subtly affect results like the `RandomState` of a `HashMap` which can change iteration
     /// order.
+    ///
+    /// `sync_with` is also how [`WriteHandle::recover`] rebuilds the copy that an `Absorb` method
+    /// panicked on. So it may be called again later, on a `self` that is in whatever state the
+    /// panic left it in, including half-way through absorbing an operation. It must overwrite all
+    /// of that state, rather than assume that `self` is still empty.
     fn sync_with(&mut self, first: &Self);
 
     /// Range at which [`WriteHandle`] tries to compress the oplog, reset each time a compression succeeds.
@@ -299,14 +350,26 @@ pub trait Absorb<O> {
     /// Defaults to [`TryCompressResult::Dependent`], which sub-optimally disables compression.
     /// Setting [`Self::MAX_COMPRESS_RANGE`](Absorb::MAX_COMPRESS_RANGE) to or leaving it at it's default of `0` is vastly more efficient for that.
     fn try_compress(prev: &mut O, next: O) -> TryCompressResult<O> {
-        // yes, unnecessary, but: makes it so that prev is not an unused variable
-        // and really matches the mental model of 'all ops are dependent'.
-        match prev {
-            _ => TryCompressResult::Dependent(next),
-        }
+        // all ops are dependent, so there is nothing to do with `prev`.
+        let _ = prev;
+        TryCompressResult::Dependent(next)
     }
 }
 
+/// An [`Absorb`] whose operations can be undone.
+///
+/// This is what [`WriteHandle::revert_last_publish`] uses to roll back the operations of the last
+/// publish.
+pub trait Invertible<O>: Absorb<O> {
+    /// Returns the operation that undoes `operation`.
+    ///
+    /// `self` is the state the data was in right before `operation` was applied to it. That is,
+    /// absorbing `operation` followed by the returned operation must leave the data exactly as it
+    /// is now. For example, the inverse of an insert into a map is a remove of the same key if
+    /// the key is not in `self`, and a re-insert of the current value otherwise.
+    fn inverse(&self, operation: &O) -> O;
+}
+
 /// Construct a new write and read handle pair from an empty data structure.
 ///
 /// The type must implement `Clone` so we can construct the second copy from the first.
@@ -0,0 +1,868 @@
+//! Durable left-right instances, whose operations are persisted to a write-ahead log.
+//!
+//! Rebuilding a large data structure from its source of truth can take a long time. With the
+//! `persist` feature, a [`WriteHandle`] can instead write the operations of every publish to an
+//! append-only log on disk with [`WriteHandle::persist`], and [`recover`] rebuilds the data
+//! structure by replaying that log after a restart.
+//!
+//! The log is a directory of segment files, each named after the first generation it holds. Each
+//! publish appends a single checksummed frame to the current segment, as part of
+//! [`publish`](WriteHandle::publish). How operations are turned into bytes is up to an
+//! [`OpCodec`].
+//!
+//! To keep the log from growing forever, [`WriteHandle::checkpoint`] writes the entire data
+//! structure to the log directory, after which the segments before it are deleted. Logs with a
+//! checkpoint are recovered with [`recover_from_checkpoint`].
+//!
+//! ```
+//! use left_right::persist::{FsyncPolicy, OpCodec};
+//! use left_right::Absorb;
+//! use std::io;
+//!
+//! struct CounterAddOp(i32);
+//! impl Absorb<CounterAddOp> for i32 {
+//!     fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+//!         *self += operation.0;
+//!     }
+//!     fn sync_with(&mut self, first: &Self) {
+//!         *self = *first
+//!     }
+//! }
+//!
+//! struct Codec;
+//! impl OpCodec<CounterAddOp> for Codec {
+//!     fn encode(&self, operation: &CounterAddOp, buf: &mut Vec<u8>) {
+//!         buf.extend_from_slice(&operation.0.to_le_bytes());
+//!     }
+//!     fn decode(&self, bytes: &[u8]) -> io::Result<CounterAddOp> {
+//!         let mut n = [0; 4];
+//!         n.copy_from_slice(bytes);
+//!         Ok(CounterAddOp(i32::from_le_bytes(n)))
+//!     }
+//! }
+//!
+//! # fn main() -> io::Result<()> {
+//! # let dir = std::env::temp_dir().join(format!("left-right-doc-{}", std::process::id()));
+//! # let _ = std::fs::remove_dir_all(&dir);
+//! let (mut w, r) = left_right::recover::<i32, _, _>(&dir, Codec, FsyncPolicy::Always)?;
+//! w.append(CounterAddOp(1)).publish();
+//! drop((w, r));
+//!
+//! // ... and after a restart:
+//! let (w, r) = left_right::recover::<i32, _, _>(&dir, Codec, FsyncPolicy::Always)?;
+//! assert_eq!(*r.enter().unwrap(), 1);
+//! # drop((w, r));
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::frame;
+pub use crate::frame::OpCodec;
+use crate::{Absorb, Batch, ReadHandle, WriteHandle};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Turns the entire data structure into bytes for a checkpoint, and back again.
+///
+/// See [`WriteHandle::checkpoint`].
+pub trait StateCodec<T> {
+    /// Append the encoding of `state` to `buf`.
+    ///
+    /// Must not touch what is in `buf` already.
+    fn encode(&self, state: &T, buf: &mut Vec<u8>);
+
+    /// Decode the data structure from exactly the bytes [`encode`](Self::encode) produced for it.
+    fn decode(&self, bytes: &[u8]) -> io::Result<T>;
+}
+
+/// When the log is flushed all the way to disk with [`File::sync_data`].
+///
+/// Without a sync, a publish may be lost if the machine (but not just the process) goes down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FsyncPolicy {
+    /// Sync as part of every publish.
+    Always,
+    /// Sync after every `n` publishes, and when the log is closed.
+    EveryN(usize),
+    /// Leave it to the operating system.
+    Never,
+}
+
+const SEGMENT_EXTENSION: &str = "wal";
+const CHECKPOINT_EXTENSION: &str = "checkpoint";
+
+fn file_path(dir: &Path, generation: u64, extension: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", generation, extension))
+}
+
+/// Returns the files in `dir` with the given extension along with the generation they are named
+/// after, in order.
+fn files(dir: &Path, extension: &str) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new(extension)) {
+            continue;
+        }
+        if let Some(generation) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            files.push((generation, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Make sure the files in `dir` themselves survive a crash, not just their contents.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// The segment files of a log.
+///
+/// Shared between the publish hook that appends to the current segment, and the [`WriteHandle`]
+/// that rotates segments when it [checkpoints](WriteHandle::checkpoint).
+pub(crate) struct Segments {
+    dir: PathBuf,
+    /// The generation the current segment is named after.
+    first: u64,
+    file: File,
+    fsync: FsyncPolicy,
+    unsynced: usize,
+}
+
+pub(crate) type SharedSegments = Arc<Mutex<Segments>>;
+
+impl Segments {
+    /// Start a new segment in `dir` for the generations from `first` onwards.
+    fn create(dir: &Path, first: u64, fsync: FsyncPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(file_path(dir, first, SEGMENT_EXTENSION))?;
+        if fsync != FsyncPolicy::Never {
+            sync_dir(dir)?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            first,
+            file,
+            fsync,
+            unsynced: 0,
+        })
+    }
+
+    fn append(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.file.write_all(frame)?;
+        self.unsynced += 1;
+        let sync = match self.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::EveryN(n) => self.unsynced >= n,
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Continue in a new segment for the generations from `first` onwards.
+    fn rotate(&mut self, first: u64) -> io::Result<()> {
+        if first == self.first {
+            // nothing has been logged since the current segment was started.
+            return Ok(());
+        }
+        let new = Self::create(&self.dir, first, self.fsync)?;
+        // the old segment is dropped (and synced) here.
+        *self = new;
+        Ok(())
+    }
+}
+
+impl Drop for Segments {
+    fn drop(&mut self) {
+        if self.unsynced != 0 && self.fsync != FsyncPolicy::Never {
+            // there is no one to report this to, and the next recovery will notice anything
+            // that did not make it to disk.
+            let _ = self.file.sync_data();
+        }
+    }
+}
+
+fn lock(segments: &SharedSegments) -> MutexGuard<'_, Segments> {
+    // every update leaves the segments in a usable state, even if it fails.
+    segments.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Log<C> {
+    segments: SharedSegments,
+    codec: C,
+    buf: Vec<u8>,
+}
+
+impl<C> Log<C> {
+    fn append<O>(&mut self, batch: Batch<'_, O>) -> io::Result<()>
+    where
+        C: OpCodec<O>,
+    {
+        self.buf.clear();
+        let codec = &self.codec;
+        frame::encode(
+            batch.generation() as u64,
+            batch.high_water_mark(),
+            batch,
+            |op, buf| codec.encode(op, buf),
+            &mut self.buf,
+        );
+        lock(&self.segments).append(&self.buf)
+    }
+}
+
+impl<T, O> WriteHandle<T, O>
+where
+    T: Absorb<O>,
+    O: 'static,
+{
+    /// Write the operations of every publish to a log in `dir`, so that the data can be rebuilt
+    /// with [`recover`] later.
+    ///
+    /// The log only has the operations, and [`recover`] replays them onto `T::default()`. So this
+    /// must be called before any operations are appended, and usually it is easier to just start
+    /// out with [`recover`], which also sets up the log on a fresh `dir`. The directory is
+    /// created if it does not exist yet.
+    ///
+    /// Returns an error if `dir` already holds a log, or if something has been appended or
+    /// published already. Otherwise, this publishes once, since [publish hooks](Self::on_publish)
+    /// can only be registered after the first publish. Nothing has been appended yet, so readers
+    /// see no difference.
+    ///
+    /// # Panics
+    ///
+    /// Once this returns, [`publish`](Self::publish) panics if the operations cannot be written
+    /// to the log. The new operations are still visible to readers in that case, but every later
+    /// publish panics without making anything visible, since the log could no longer be replayed
+    /// past the failed write. The data can then be rebuilt from the log with [`recover`].
+    pub fn persist<C>(
+        &mut self,
+        dir: impl AsRef<Path>,
+        codec: C,
+        fsync: FsyncPolicy,
+    ) -> io::Result<&mut Self>
+    where
+        C: OpCodec<O> + Send + 'static,
+    {
+        let dir = dir.as_ref();
+        if !self.is_pristine() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only an instance that nothing has been appended to or published yet can be \
+                 persisted",
+            ));
+        }
+        fs::create_dir_all(dir)?;
+        if !files(dir, SEGMENT_EXTENSION)?.is_empty()
+            || !files(dir, CHECKPOINT_EXTENSION)?.is_empty()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "directory already holds a log; use `left_right::recover` instead",
+            ));
+        }
+
+        // hooks can only be registered after the first publish, so the log starts at the
+        // generation after that. nothing has been appended, so that publish has nothing to log.
+        // the segment is created before publishing though, so that if that fails, the instance
+        // is left just as it was.
+        let first = self.generation() as u64 + 2;
+        let segments = Segments::create(dir, first, fsync)?;
+        self.publish();
+        debug_assert_eq!(self.generation() as u64 + 1, first);
+        self.attach(segments, codec);
+        Ok(self)
+    }
+
+    fn attach<C>(&mut self, segments: Segments, codec: C)
+    where
+        C: OpCodec<O> + Send + 'static,
+    {
+        let segments = Arc::new(Mutex::new(segments));
+        self.log = Some(Arc::clone(&segments));
+        let mut log = Log {
+            segments,
+            codec,
+            buf: Vec::new(),
+        };
+        let failed = Arc::new(AtomicBool::new(false));
+        self.set_log_failed_flag(Arc::clone(&failed));
+        self.on_publish(Box::new(move |batch| {
+            // every generation gets a frame, even if it has no operations, so that recovery can
+            // tell a gap in the log from a publish that had nothing to log.
+            if let Err(e) = log.append(batch) {
+                // part of the frame may have made it to disk, and anything after it could not be
+                // replayed. so refuse any further publishes rather than leave a gap in the log.
+                failed.store(true, Ordering::Release);
+                panic!(
+                    "failed to log generation {} of left-right instance: {}",
+                    batch.generation(),
+                    e
+                );
+            }
+        }));
+    }
+
+    /// Write the data as readers currently see it to a checkpoint in the log, and delete
+    /// everything the checkpoint makes redundant.
+    ///
+    /// The data is encoded with `codec` while holding a [`ReadGuard`](crate::ReadGuard) on the
+    /// read copy, so readers carry on as usual. Once the checkpoint is safely on disk, the log
+    /// continues in a new segment, and all older segments and checkpoints are deleted. From then
+    /// on, the log has to be recovered with [`recover_from_checkpoint`], which loads the
+    /// checkpoint and only replays the operations published after it.
+    ///
+    /// Returns the generation of the checkpoint, or an error if the instance is not
+    /// [persisted](Self::persist), is [poisoned](ReadHandle::is_poisoned), or if writing the
+    /// checkpoint fails. In that last case, the log is left as it was. Once the checkpoint is on
+    /// disk though, it is the one that [`recover_from_checkpoint`] starts from. If starting the
+    /// new segment fails after that, the error is still returned, and the log carries on in the
+    /// current one. Files that could not be deleted are left behind, and are cleaned up by a
+    /// later checkpoint.
+    pub fn checkpoint<S>(&mut self, codec: &S) -> io::Result<usize>
+    where
+        S: StateCodec<T>,
+    {
+        let segments = match self.log {
+            Some(ref segments) => Arc::clone(segments),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot checkpoint an instance that is not persisted",
+                ))
+            }
+        };
+        let mut segments = lock(&segments);
+
+        // we hold on to `&mut self`, so nothing is published until we are done, and the read copy
+        // is the one of this generation.
+        let generation = self.generation();
+        let mut buf = Vec::new();
+        {
+            let state = self
+                .try_enter()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let start = frame::begin(&mut buf);
+            codec.encode(&state, &mut buf);
+            frame::seal(&mut buf, start, generation as u64, self.high_water_mark());
+        }
+
+        // write it out under a temporary name first, so a crash never leaves a torn checkpoint.
+        let path = file_path(&segments.dir, generation as u64, CHECKPOINT_EXTENSION);
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, &path)?;
+        sync_dir(&segments.dir)?;
+
+        // the current segment only has generations up to this one, so start a new one.
+        segments.rotate(generation as u64 + 1)?;
+        let current = segments.first;
+        // the checkpoint is in place, so failing to clean up after it is no reason to report that
+        // it failed. recovery skips whatever is left behind, and the next checkpoint tries again.
+        for (_, old) in files(&segments.dir, SEGMENT_EXTENSION).unwrap_or_default() {
+            if old != file_path(&segments.dir, current, SEGMENT_EXTENSION) {
+                let _ = fs::remove_file(old);
+            }
+        }
+        for (_, old) in files(&segments.dir, CHECKPOINT_EXTENSION).unwrap_or_default() {
+            if old != path {
+                let _ = fs::remove_file(old);
+            }
+        }
+        Ok(generation)
+    }
+}
+
+fn gap() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "log is missing generations, or has them out of order",
+    )
+}
+
+/// Rebuild a left-right instance from the log in `dir`, and keep logging to it.
+///
+/// Replays all the operations in the log onto `T::default()`, publishes the result, and sets up
+/// the returned [`WriteHandle`] to continue logging to `dir` as if it had been
+/// [persisted](WriteHandle::persist) all along. Generations pick up where the log left off. If
+/// `dir` does not hold a log yet, this sets one up on a new instance.
+///
+/// If the process crashed part-way through writing a frame, that frame is discarded, and the log
+/// is truncated to the last complete one.
+///
+/// Returns an error if reading the log fails, if any frame is corrupt, if generations are
+/// missing from the log, or if the codec fails to decode an operation. Logs with a
+/// [checkpoint](WriteHandle::checkpoint) must be recovered with [`recover_from_checkpoint`]
+/// instead.
+pub fn recover<T, O, C>(
+    dir: impl AsRef<Path>,
+    codec: C,
+    fsync: FsyncPolicy,
+) -> io::Result<(WriteHandle<T, O>, ReadHandle<T>)>
+where
+    T: Absorb<O> + Default,
+    O: 'static,
+    C: OpCodec<O> + Send + 'static,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    if !files(dir, CHECKPOINT_EXTENSION)?.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "log has a checkpoint; use `left_right::persist::recover_from_checkpoint` instead",
+        ));
+    }
+    let (w, r) = crate::new::<T, O>();
+    replay(dir, w, r, (0, None), codec, fsync)
+}
+
+/// Rebuild a left-right instance from the latest checkpoint and the log in `dir`, and keep
+/// logging to it.
+///
+/// This is like [`recover`], except that the data is decoded from the latest
+/// [checkpoint](WriteHandle::checkpoint) with `state`, and only the operations published after
+/// that checkpoint are replayed onto it. If there is no checkpoint, this replays the entire log
+/// onto `T::default()`, just like [`recover`].
+pub fn recover_from_checkpoint<T, O, C, S>(
+    dir: impl AsRef<Path>,
+    codec: C,
+    state: &S,
+    fsync: FsyncPolicy,
+) -> io::Result<(WriteHandle<T, O>, ReadHandle<T>)>
+where
+    T: Absorb<O> + Default + Clone,
+    O: 'static,
+    C: OpCodec<O> + Send + 'static,
+    S: StateCodec<T>,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let checkpoint = match files(dir, CHECKPOINT_EXTENSION)?.pop() {
+        Some((_, path)) => {
+            let mut bytes = Vec::new();
+            File::open(path)?.read_to_end(&mut bytes)?;
+            let frame = frame::read(&mut &bytes[..])?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            let t = state.decode(&frame.payload)?;
+            Some((frame.generation, frame.high_water_mark, t))
+        }
+        None => None,
+    };
+
+    match checkpoint {
+        Some((generation, seq, t)) => {
+            let (w, r) = crate::new_from_empty::<T, O>(t);
+            replay(dir, w, r, (generation, seq), codec, fsync)
+        }
+        None => {
+            let (w, r) = crate::new::<T, O>();
+            replay(dir, w, r, (0, None), codec, fsync)
+        }
+    }
+}
+
+/// Replay the operations published after generation `since` in the log in `dir` onto the fresh
+/// instance `w`, and attach the log to it.
+///
+/// `since` also has the high-water mark as of that generation.
+fn replay<T, O, C>(
+    dir: &Path,
+    mut w: WriteHandle<T, O>,
+    r: ReadHandle<T>,
+    (since, mut seq): (u64, Option<u64>),
+    codec: C,
+    fsync: FsyncPolicy,
+) -> io::Result<(WriteHandle<T, O>, ReadHandle<T>)>
+where
+    T: Absorb<O>,
+    O: 'static,
+    C: OpCodec<O> + Send + 'static,
+{
+    // a checkpoint that was not renamed into place is no checkpoint at all.
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("tmp")) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let segments = files(dir, SEGMENT_EXTENSION)?;
+    if segments.is_empty() && since == 0 {
+        w.persist(dir, codec, fsync)?;
+        return Ok((w, r));
+    }
+
+    // a log starts right after the first publish, which is generation 1, or after its checkpoint.
+    let mut last = since.max(1);
+    for (i, (_, path)) in segments.iter().enumerate() {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut valid = 0;
+        loop {
+            let frame = match frame::read(&mut reader) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) if i == segments.len() - 1 && e.kind() == io::ErrorKind::UnexpectedEof => {
+                    // the last write did not make it to disk in full, so it was never durable.
+                    // a complete frame with a bad checksum is not torn though, but corrupt.
+                    let file = OpenOptions::new().write(true).open(path)?;
+                    file.set_len(valid)?;
+                    file.sync_all()?;
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            valid += frame.encoded_len() as u64;
+            if frame.generation <= since {
+                // already in the checkpoint, which crashed before it could delete this segment.
+                continue;
+            }
+            if frame.generation != last + 1 {
+                return Err(gap());
+            }
+            // during the first publish cycle, operations are applied directly to the data.
+            w.extend(frame.ops(|bytes| codec.decode(bytes))?);
+            last = frame.generation;
+            seq = frame.high_water_mark;
+        }
+
+        if valid == 0 {
+            // a segment that never got a frame would get in the way of the one we start below.
+            fs::remove_file(path)?;
+        }
+    }
+
+    w.resume_high_water_mark(seq);
+    w.publish();
+    w.resume_generation(last as usize);
+    let segments = Segments::create(dir, w.generation() as u64 + 1, fsync)?;
+    w.attach(segments, codec);
+    Ok((w, r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::I32Codec;
+    use crate::TryCompressResult;
+    include!("./utilities.rs");
+
+    struct State;
+    impl StateCodec<i32> for State {
+        fn encode(&self, state: &i32, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(&state.to_le_bytes());
+        }
+        fn decode(&self, bytes: &[u8]) -> io::Result<i32> {
+            I32Codec.decode(bytes).map(|op: CounterAddOp| op.0)
+        }
+    }
+
+    fn open(dir: &Path) -> io::Result<(WriteHandle<i32, CounterAddOp>, ReadHandle<i32>)> {
+        recover(dir, I32Codec, FsyncPolicy::Always)
+    }
+
+    #[test]
+    fn recover_replays_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, r) = open(dir.path()).unwrap();
+        w.append(CounterAddOp(1)).append(CounterAddOp(2)).publish();
+        w.publish();
+        w.append(CounterAddOp(3)).publish();
+        // empty publishes are logged too, so the generations after a recovery line up
+        w.publish();
+        let generation = r.generation();
+        drop((w, r));
+
+        let (mut w, r) = open(dir.path()).unwrap();
+        assert_eq!(*r.enter().unwrap(), 6);
+        assert_eq!(r.generation(), generation);
+        w.append(CounterAddOp(4)).publish();
+        assert_eq!(r.generation(), generation + 1);
+        drop((w, r));
+
+        let (_w, r) = open(dir.path()).unwrap();
+        assert_eq!(*r.enter().unwrap(), 10);
+        assert_eq!(files(dir.path(), SEGMENT_EXTENSION).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn torn_tail_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, _r) = open(dir.path()).unwrap();
+        w.append(CounterAddOp(1)).publish();
+        w.append(CounterAddOp(2)).publish();
+        // dropping the writer publishes once more, to bring both copies up to date
+        let (_, path) = files(dir.path(), SEGMENT_EXTENSION).unwrap().pop().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        drop(w);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+        drop(file);
+
+        let (mut w, r) = open(dir.path()).unwrap();
+        assert_eq!(*r.enter().unwrap(), 1);
+        w.append(CounterAddOp(5)).publish();
+        drop(w);
+
+        // the truncated segment is no longer the last one, and is still readable
+        let (_w, r) = open(dir.path()).unwrap();
+        assert_eq!(*r.enter().unwrap(), 6);
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, _r) = open(dir.path()).unwrap();
+        w.append(CounterAddOp(1)).publish();
+        drop(w);
+        let (mut w, _r) = open(dir.path()).unwrap();
+        w.append(CounterAddOp(2)).publish();
+        drop(w);
+
+        // flip a bit in the operation of the first segment
+        let (_, path) = files(dir.path(), SEGMENT_EXTENSION).unwrap().remove(0);
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let e = open(dir.path()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn corruption_before_the_tail_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, _r) = open(dir.path()).unwrap();
+        w.append(CounterAddOp(1)).publish();
+        w.append(CounterAddOp(2)).publish();
+        drop(w);
+
+        // flip a bit in the operation of the first frame of the last segment
+        let (_, path) = files(dir.path(), SEGMENT_EXTENSION).unwrap().pop().unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let len = bytes.len();
+        bytes[len / 2 - 1] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let e = open(dir.path()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        // the valid frame after it must not be thrown away
+        assert_eq!(fs::metadata(&path).unwrap().len(), len as u64);
+    }
+
+    #[test]
+    fn bad_checksum_at_the_tail_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, _r) = open(dir.path()).unwrap();
+        w.append(CounterAddOp(1)).publish();
+        w.append(CounterAddOp(2)).publish();
+        drop(w);
+
+        // the last frame is complete, so it was not torn by a crash, but it is corrupt
+        let (_, path) = files(dir.path(), SEGMENT_EXTENSION).unwrap().pop().unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let len = bytes.len();
+        bytes[len - 1] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let e = open(dir.path()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len as u64);
+    }
+
+    #[test]
+    fn missing_generations_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..3 {
+            let (mut w, _r) = open(dir.path()).unwrap();
+            w.append(CounterAddOp(i)).publish();
+        }
+
+        let (_, path) = files(dir.path(), SEGMENT_EXTENSION).unwrap().remove(1);
+        fs::remove_file(path).unwrap();
+
+        let e = open(dir.path()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn failed_write_refuses_later_publishes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("read-only");
+        File::create(&path).unwrap();
+        let (mut w, r) = crate::new::<i32, CounterAddOp>();
+        w.publish();
+        let segments = Segments {
+            dir: dir.path().to_path_buf(),
+            first: 2,
+            file: File::open(&path).unwrap(),
+            fsync: FsyncPolicy::Never,
+            unsynced: 0,
+        };
+        w.attach(segments, I32Codec);
+
+        w.append(CounterAddOp(1));
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            w.publish();
+        }));
+        assert!(res.is_err());
+        assert_eq!(*r.enter().unwrap(), 1);
+
+        // publishing again would leave a gap in the log, so nothing more is made visible
+        let generation = r.generation();
+        w.append(CounterAddOp(2));
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            w.publish();
+        }));
+        assert!(res.is_err());
+        assert_eq!(r.generation(), generation);
+        assert_eq!(*r.enter().unwrap(), 1);
+    }
+
+    #[test]
+    fn persist_needs_fresh_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        w.publish();
+        let e = w
+            .persist(dir.path(), I32Codec, FsyncPolicy::Never)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        // operations applied before the log exists would never make it into the log
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        w.append(CounterAddOp(1));
+        let e = w
+            .persist(dir.path(), I32Codec, FsyncPolicy::Never)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        w.persist(dir.path(), I32Codec, FsyncPolicy::EveryN(2))
+            .unwrap();
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        let e = w
+            .persist(dir.path(), I32Codec, FsyncPolicy::Never)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn checkpoint_truncates_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, r) = crate::new::<i32, CounterAddOp>();
+        assert_eq!(
+            w.checkpoint(&State).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let (mut w, r2) = open(dir.path()).unwrap();
+        drop(r);
+        w.append(CounterAddOp(1)).append(CounterAddOp(2)).publish();
+        w.append(CounterAddOp(3)).publish();
+        let segment = files(dir.path(), SEGMENT_EXTENSION).unwrap().remove(0).1;
+        let stale = fs::read(&segment).unwrap();
+
+        let generation = w.checkpoint(&State).unwrap();
+        assert_eq!(generation, r2.generation());
+        // nothing was published since, so there is no need for yet another segment
+        assert_eq!(w.checkpoint(&State).unwrap(), generation);
+        assert_eq!(files(dir.path(), SEGMENT_EXTENSION).unwrap().len(), 1);
+        assert_eq!(files(dir.path(), CHECKPOINT_EXTENSION).unwrap().len(), 1);
+        assert!(!segment.exists());
+
+        w.append(CounterAddOp(4)).publish();
+        drop(w);
+
+        // pretend we crashed before the old segment was deleted
+        fs::write(&segment, stale).unwrap();
+
+        assert_eq!(
+            open(dir.path()).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let (mut w, r) = recover_from_checkpoint::<i32, CounterAddOp, _, _>(
+            dir.path(),
+            I32Codec,
+            &State,
+            FsyncPolicy::Always,
+        )
+        .unwrap();
+        assert_eq!(*r.enter().unwrap(), 10);
+        // dropping the writer published once more, to bring both copies up to date
+        assert_eq!(r.generation(), generation + 2);
+
+        w.append(CounterAddOp(5)).publish();
+        w.checkpoint(&State).unwrap();
+        drop(w);
+        let (_w, r) = recover_from_checkpoint::<i32, CounterAddOp, _, _>(
+            dir.path(),
+            I32Codec,
+            &State,
+            FsyncPolicy::Always,
+        )
+        .unwrap();
+        assert_eq!(*r.enter().unwrap(), 15);
+        assert_eq!(r.generation(), generation + 4);
+    }
+
+    #[test]
+    fn high_water_mark_is_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut w, _r) = open(dir.path()).unwrap();
+        w.append_seq(1, CounterAddOp(1));
+        w.append_seq(2, CounterAddOp(2));
+        w.publish();
+        drop(w);
+
+        let (mut w, r) = open(dir.path()).unwrap();
+        assert_eq!(r.high_water_mark(), Some(2));
+        assert!(!w.append_seq(2, CounterAddOp(2)));
+        assert!(w.append_seq(3, CounterAddOp(3)));
+        w.publish();
+        drop(w);
+
+        let (mut w, r) = open(dir.path()).unwrap();
+        assert_eq!(*r.enter().unwrap(), 6);
+        assert_eq!(r.high_water_mark(), Some(3));
+        w.append_seq(4, CounterAddOp(4));
+        w.publish();
+        w.checkpoint(&State).unwrap();
+        drop(w);
+
+        let (mut w, r) = recover_from_checkpoint::<i32, CounterAddOp, _, _>(
+            dir.path(),
+            I32Codec,
+            &State,
+            FsyncPolicy::Never,
+        )
+        .unwrap();
+        assert_eq!(*r.enter().unwrap(), 10);
+        assert_eq!(r.high_water_mark(), Some(4));
+        assert!(!w.append_seq(4, CounterAddOp(4)));
+    }
+}
@@ -1,19 +1,76 @@
-use crate::sync::{fence, Arc, AtomicPtr, AtomicUsize, Ordering};
+use crate::epochs::{EpochRegistry, Registration};
+use crate::shared::Shared;
+use crate::sync::{fence, Arc, Ordering};
 use std::cell::Cell;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ptr::NonNull;
+use std::rc::Rc;
 
 // To make [`WriteHandle`] and friends work.
 #[cfg(doc)]
 use crate::WriteHandle;
 
 mod guard;
-pub use guard::ReadGuard;
+pub use guard::{OwnedReadGuard, ReadGuard, ZippedReadGuard};
 
 mod factory;
 pub use factory::ReadHandleFactory;
 
+mod snapshot;
+pub use snapshot::{Snapshot, VersionGone};
+
+mod local;
+pub use local::SyncReadHandle;
+
+mod mapped;
+pub use mapped::{MappedReadHandle, MappedReadHandleFactory};
+
+mod erased;
+pub use erased::DynReadHandle;
+
+/// What the writer knows about one of its readers.
+///
+/// Returned by [`WriteHandle::readers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct ReaderInfo {
+    /// The index of the slot the reader's epoch is kept in.
+    ///
+    /// This stays the same for as long as the [`ReadHandle`] lives, but may be reused by another
+    /// handle after that.
+    pub index: usize,
+    /// Whether the reader currently holds a [`ReadGuard`].
+    pub active: bool,
+    /// The number of times the reader has entered.
+    ///
+    /// See [`ReadHandle::enter_count`].
+    pub enters: usize,
+}
+
+/// The reason why [`ReadHandle::try_enter`] could not hand out a [`ReadGuard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EnterError {
+    /// The [`WriteHandle`] has been dropped, and took the backing data down with it.
+    WriterDropped,
+    /// An [`Absorb`](crate::Absorb) method panicked while the writer was applying operations.
+    ///
+    /// Readers can enter again once the writer calls [`WriteHandle::recover`].
+    Poisoned,
+}
+
+impl fmt::Display for EnterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnterError::WriterDropped => f.write_str("the left-right writer has been dropped"),
+            EnterError::Poisoned => f.write_str("the left-right instance is poisoned"),
+        }
+    }
+}
+
+impl std::error::Error for EnterError {}
+
 /// A read handle to a left-right guarded data structure.
 ///
 /// To use a handle, first call [`enter`](Self::enter) to acquire a [`ReadGuard`]. This is similar
@@ -34,13 +91,13 @@ pub use factory::ReadHandleFactory;
 /// `ReadHandle` per reader, which guarantees that you do not accidentally ruin your performance.
 ///
 /// You can create a new, independent `ReadHandle` either by cloning an existing handle or by using
-/// a [`ReadHandleFactory`]. Note, however, that creating a new handle through either of these
-/// mechanisms _does_ take a lock, and may therefore become a bottleneck if you do it frequently.
+/// a [`ReadHandleFactory`]. Creating (and dropping) a handle never waits for the writer, but it
+/// does have to find a free slot among those of all other readers, so it is not free either. If
+/// you need to read from `&self` on many threads, use a [`SyncReadHandle`], which does this
+/// once per thread.
 pub struct ReadHandle<T> {
-    pub(crate) inner: Arc<AtomicPtr<T>>,
-    pub(crate) epochs: crate::Epochs,
-    epoch: Arc<AtomicUsize>,
-    epoch_i: usize,
+    pub(crate) inner: Arc<Shared<T>>,
+    epoch: Registration,
     enters: Cell<usize>,
 
     // `ReadHandle` is _only_ Send if T is Sync. If T is !Sync, then it's not okay for us to expose
@@ -53,10 +110,8 @@ unsafe impl<T> Send for ReadHandle<T> where T: Sync {}
 
 impl<T> Drop for ReadHandle<T> {
     fn drop(&mut self) {
-        // epoch must already be even for us to have &mut self,
-        // so okay to lock since we're not holding up the epoch anyway.
-        let e = self.epochs.lock().unwrap().remove(self.epoch_i);
-        assert!(Arc::ptr_eq(&e, &self.epoch));
+        // epoch must already be even for us to have &mut self, so it's okay for our slot to be
+        // released (along with `self.epoch`) and handed to another reader.
         assert_eq!(self.enters.get(), 0);
     }
 }
@@ -64,7 +119,7 @@ impl<T> Drop for ReadHandle<T> {
 impl<T> fmt::Debug for ReadHandle<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ReadHandle")
-            .field("epochs", &self.epochs)
+            .field("epochs", self.epochs())
             .field("epoch", &self.epoch)
             .finish()
     }
@@ -72,39 +127,40 @@ impl<T> fmt::Debug for ReadHandle<T> {
 
 impl<T> Clone for ReadHandle<T> {
     fn clone(&self) -> Self {
-        ReadHandle::new_with_arc(Arc::clone(&self.inner), Arc::clone(&self.epochs))
+        ReadHandle::new_with_arc(Arc::clone(&self.inner), Arc::clone(self.epochs()))
     }
 }
 
 impl<T> ReadHandle<T> {
     pub(crate) fn new(inner: T, epochs: crate::Epochs) -> Self {
         let store = Box::into_raw(Box::new(inner));
-        let inner = Arc::new(AtomicPtr::new(store));
+        let inner = Arc::new(Shared::new(store));
         Self::new_with_arc(inner, epochs)
     }
 
-    fn new_with_arc(inner: Arc<AtomicPtr<T>>, epochs: crate::Epochs) -> Self {
+    fn new_with_arc(inner: Arc<Shared<T>>, epochs: crate::Epochs) -> Self {
         // tell writer about our epoch tracker
-        let epoch = Arc::new(AtomicUsize::new(0));
-        // okay to lock, since we're not holding up the epoch
-        let epoch_i = epochs.lock().unwrap().insert(Arc::clone(&epoch));
+        let epoch = EpochRegistry::register(&epochs);
 
         Self {
-            epochs,
             epoch,
-            epoch_i,
             enters: Cell::new(0),
             inner,
             _unimpl_send: PhantomData,
         }
     }
 
+    pub(crate) fn epochs(&self) -> &crate::Epochs {
+        self.epoch.registry()
+    }
+
     /// Create a [`ReadHandleFactory`] which is `Send` & `Sync` and can be shared across threads to create
     /// additional [`ReadHandle`] instances.
     pub fn factory(&self) -> ReadHandleFactory<T> {
         ReadHandleFactory {
             inner: Arc::clone(&self.inner),
-            epochs: Arc::clone(&self.epochs),
+            epochs: Arc::clone(self.epochs()),
+            local: local::LocalKey::new(),
         }
     }
 }
@@ -115,20 +171,36 @@ impl<T> ReadHandle<T> {
     /// While the guard lives, the [`WriteHandle`] cannot proceed with a call to
     /// [`WriteHandle::publish`], so no queued operations will become visible to _any_ reader.
     ///
-    /// If the `WriteHandle` has been dropped, this function returns `None`.
+    /// If the `WriteHandle` has been dropped, or if the instance has been
+    /// [poisoned](Self::is_poisoned), this function returns `None`. Use
+    /// [`try_enter`](Self::try_enter) to find out which of the two happened.
     pub fn enter(&self) -> Option<ReadGuard<'_, T>> {
+        self.try_enter().ok()
+    }
+
+    /// Take out a guarded live reference to the read copy of the `T`, or learn why that is not
+    /// possible.
+    ///
+    /// This is the same as [`enter`](Self::enter), except that it returns an [`EnterError`]
+    /// describing why no guard could be handed out.
+    pub fn try_enter(&self) -> Result<ReadGuard<'_, T>, EnterError> {
+        if self.is_poisoned() {
+            return Err(EnterError::Poisoned);
+        }
+
         let enters = self.enters.get();
         if enters != 0 {
             // We have already locked the epoch.
             // Just give out another guard.
-            let r_handle = self.inner.load(Ordering::Acquire);
+            let r_handle = self.inner.ptr.load(Ordering::Acquire);
             // since we previously bumped our epoch, this pointer will remain valid until we bump
             // it again, which only happens when the last ReadGuard is dropped.
             let r_handle = unsafe { r_handle.as_ref() };
 
             return if let Some(r_handle) = r_handle {
                 self.enters.set(enters + 1);
-                Some(ReadGuard {
+                self.epoch.count_enter();
+                Ok(ReadGuard {
                     handle: guard::ReadHandleState::from(self),
                     t: r_handle,
                 })
@@ -165,13 +237,13 @@ impl<T> ReadHandle<T> {
         // in all cases, using a pointer we read *after* updating our epoch is safe.
 
         // so, update our epoch tracker.
-        self.epoch.fetch_add(1, Ordering::AcqRel);
+        self.epoch.epoch().fetch_add(1, Ordering::AcqRel);
 
         // ensure that the pointer read happens strictly after updating the epoch
         fence(Ordering::SeqCst);
 
         // then, atomically read pointer, and use the copy being pointed to
-        let r_handle = self.inner.load(Ordering::Acquire);
+        let r_handle = self.inner.ptr.load(Ordering::Acquire);
 
         // since we bumped our epoch, this pointer will remain valid until we bump it again
         let r_handle = unsafe { r_handle.as_ref() };
@@ -180,21 +252,174 @@ impl<T> ReadHandle<T> {
             // add a guard to ensure we restore read parity even if we panic
             let enters = self.enters.get() + 1;
             self.enters.set(enters);
-            Some(ReadGuard {
+            self.epoch.count_enter();
+            Ok(ReadGuard {
                 handle: guard::ReadHandleState::from(self),
                 t: r_handle,
             })
         } else {
             // the writehandle has been dropped, and so has both copies,
-            // so restore parity and return None
-            self.epoch.fetch_add(1, Ordering::AcqRel);
-            None
+            // so restore parity and return an error
+            self.epoch.epoch().fetch_add(1, Ordering::AcqRel);
+            Err(EnterError::WriterDropped)
+        }
+    }
+
+    /// Take out a guarded live reference to the read copy of the `T` that keeps this handle alive.
+    ///
+    /// This works just like [`enter`](Self::enter), except that the returned [`OwnedReadGuard`]
+    /// does not borrow the handle. Instead, it holds on to the `Rc` the handle lives in. The guard
+    /// counts towards the same reentrant enters as guards returned by `enter`, so the handle's
+    /// epoch is only released once the last guard of either kind is dropped.
+    pub fn enter_owned(self: &Rc<Self>) -> Option<OwnedReadGuard<T>> {
+        self.try_enter_owned().ok()
+    }
+
+    /// Take out a guarded live reference to the read copy of the `T` that keeps this handle
+    /// alive, or learn why that is not possible.
+    ///
+    /// See [`enter_owned`](Self::enter_owned) and [`try_enter`](Self::try_enter).
+    pub fn try_enter_owned(self: &Rc<Self>) -> Result<OwnedReadGuard<T>, EnterError> {
+        let guard = self.try_enter()?;
+        let t = NonNull::from(guard.t);
+        // the owned guard takes over the enter from the borrowed guard
+        std::mem::forget(guard);
+        Ok(OwnedReadGuard {
+            t,
+            handle: Rc::clone(self),
+        })
+    }
+
+    /// Take an owned snapshot of the read copy of the `T`.
+    ///
+    /// The returned [`Snapshot`] does not hold up the [`WriteHandle`] in any way, so it may be
+    /// kept around for as long as you like. It does not observe any changes published after it was
+    /// taken.
+    ///
+    /// Snapshots are shared: all readers that ask for a snapshot of the same published version of
+    /// the data get the same reference-counted copy, so only the first of them pays for the
+    /// clone. That clone happens while a [`ReadGuard`] is held, which means that the writer cannot
+    /// publish until it's done. To avoid that, use [`request_snapshot`](Self::request_snapshot)
+    /// to have the writer do the cloning instead. If `T` is itself cheap to clone (say, because
+    /// it is a persistent data structure that shares its contents between clones), none of this
+    /// matters much.
+    ///
+    /// The data must be `Send` and `Sync`, since the snapshot is shared with other readers and may
+    /// be dropped by the writer.
+    pub fn snapshot(&self) -> Result<Snapshot<T>, EnterError>
+    where
+        T: Clone + Send + Sync,
+    {
+        let guard = self.try_enter()?;
+        let copy = &*guard as *const T as usize;
+        let latest = |snapshots: &crate::shared::Snapshots<T>| match snapshots.latest {
+            Some((of, ref t)) if of == copy => Some(std::sync::Arc::clone(t)),
+            _ => None,
+        };
+        if let Some(t) = latest(&self.inner.snapshots()) {
+            return Ok(Snapshot { t });
         }
+
+        // clone without holding the lock, so other readers are not held up by it. another reader
+        // may beat us to it, in which case we use theirs, so that snapshots stay shared.
+        let t = std::sync::Arc::new(T::clone(&guard));
+        let mut snapshots = self.inner.snapshots();
+        if let Some(t) = latest(&snapshots) {
+            return Ok(Snapshot { t });
+        }
+        snapshots.latest = Some((copy, std::sync::Arc::clone(&t)));
+        Ok(Snapshot { t })
+    }
+
+    /// Ask the writer to take a snapshot of the data the next time it publishes.
+    ///
+    /// The writer clones its copy of the data right before it makes it visible to readers, so
+    /// calls to [`snapshot`](Self::snapshot) after that return the writer's clone instead of
+    /// cloning the data while holding up the writer. If the writer publishes again before anyone
+    /// has called `snapshot`, that clone is discarded.
+    pub fn request_snapshot(&self)
+    where
+        T: Clone + Send + Sync,
+    {
+        self.inner.snapshots().requested = Some(T::clone);
     }
 
-    /// Returns true if the [`WriteHandle`] has been dropped.
+    /// Get a snapshot of the version of the data that was published as `generation`.
+    ///
+    /// This only works for versions the writer retains, which it only does once
+    /// [`WriteHandle::retain_versions`] has been called. Unlike [`snapshot`](Self::snapshot), this
+    /// never clones the data, or holds up the writer.
+    ///
+    /// Returns an error if the version is no longer retained, or has not been published yet.
+    pub fn enter_at(&self, generation: usize) -> Result<Snapshot<T>, VersionGone> {
+        let snapshots = self.inner.snapshots();
+        let versions = match snapshots.retained {
+            Some(ref retained) => &retained.versions,
+            None => return Err(VersionGone { oldest: None }),
+        };
+        versions
+            .iter()
+            .find(|&&(g, _)| g == generation)
+            .map(|(_, t)| Snapshot {
+                t: std::sync::Arc::clone(t),
+            })
+            .ok_or(VersionGone {
+                oldest: versions.front().map(|&(g, _)| g),
+            })
+    }
+
+    /// Returns true if the [`WriteHandle`] has been dropped, and took the data with it.
+    ///
+    /// A writer that was [frozen](WriteHandle::freeze), or that
+    /// [froze when its thread panicked](WriteHandle::freeze_on_panic), left the data behind for
+    /// the readers instead. This returns false for those; see [`is_frozen`](Self::is_frozen).
     pub fn was_dropped(&self) -> bool {
-        self.inner.load(Ordering::Acquire).is_null()
+        self.inner.ptr.load(Ordering::Acquire).is_null()
+    }
+
+    /// Returns the number of times [`WriteHandle::publish`] has been called on this left-right
+    /// instance.
+    ///
+    /// Note that the generation may change at any time, so a guard taken out right after calling
+    /// this may already observe a later one.
+    pub fn generation(&self) -> usize {
+        self.inner.generation.load(Ordering::Acquire)
+    }
+
+    /// Returns the highest sequence number passed to [`WriteHandle::append_seq`] as of the last
+    /// publish, or `None` if there has not been one.
+    ///
+    /// Like the [generation](Self::generation), this may change at any time. It is updated right
+    /// after readers are pointed at the new data, so a guard taken out after calling this always
+    /// has all the operations up to the returned sequence number.
+    pub fn high_water_mark(&self) -> Option<u64> {
+        *self.inner.high_water_mark()
+    }
+
+    /// Returns the number of times a guard was successfully taken out through this handle.
+    ///
+    /// Every call to [`enter`](Self::enter) and its variants that hands out a guard counts, including
+    /// reentrant ones. The count is also available to the writer through
+    /// [`WriteHandle::readers`].
+    pub fn enter_count(&self) -> usize {
+        self.epoch.enters()
+    }
+
+    /// Returns true if the writer has been [frozen](WriteHandle::freeze).
+    ///
+    /// The data remains readable through this handle, but will not change again unless a new
+    /// writer [takes over](ReadHandleFactory::take_writer).
+    pub fn is_frozen(&self) -> bool {
+        self.inner.frozen.load(Ordering::Acquire)
+    }
+
+    /// Returns true if an [`Absorb`](crate::Absorb) method panicked while the writer was applying
+    /// operations.
+    ///
+    /// The two copies of the data may have diverged in that case, so readers are kept out until
+    /// the writer calls [`WriteHandle::recover`].
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.poisoned.load(Ordering::Acquire)
     }
 
     /// Returns a raw pointer to the read copy of the data.
@@ -205,7 +430,7 @@ impl<T> ReadHandle<T> {
     ///
     /// Casting this pointer to `&mut` is never safe.
     pub fn raw_handle(&self) -> Option<NonNull<T>> {
-        NonNull::new(self.inner.load(Ordering::Acquire))
+        NonNull::new(self.inner.ptr.load(Ordering::Acquire))
     }
 }
 
@@ -244,5 +469,393 @@ impl<T> ReadHandle<T> {
 ///
 /// is_send::<ReadHandle<std::cell::Cell<u64>>>()
 /// ```
+///
+/// It does not have to be `Send` as well:
+///
+/// ```
+/// use left_right::ReadHandle;
+///
+/// fn is_send<T: Send>() {}
+///
+/// is_send::<ReadHandle<std::sync::MutexGuard<'static, u64>>>()
+/// ```
+///
+/// And `ReadHandleFactory` is both `Send` and `Sync`, whatever the wrapped type:
+///
+/// ```
+/// use left_right::ReadHandleFactory;
+///
+/// fn is_send_sync<T: Send + Sync>() {}
+///
+/// is_send_sync::<ReadHandleFactory<std::rc::Rc<u64>>>()
+/// ```
 #[allow(dead_code)]
 struct CheckReadHandleSendNotSync;
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::Ordering;
+    use crate::{Absorb, OwnedReadGuard, ReadGuard, TryCompressResult};
+    use std::rc::Rc;
+    include!("./utilities.rs");
+
+    fn epoch<T>(r: &super::ReadHandle<T>) -> usize {
+        r.epoch.epoch().load(Ordering::Acquire)
+    }
+
+    #[test]
+    fn owned_guard_outlives_borrow() {
+        fn get(factory: &crate::ReadHandleFactory<i32>) -> OwnedReadGuard<i32> {
+            // the handle only lives as long as the guard does
+            Rc::new(factory.handle()).enter_owned().unwrap()
+        }
+
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(42));
+        w.publish();
+        let guard = get(&r.factory());
+        assert_eq!(*guard, 42);
+        drop(guard);
+        // the guard released its epoch, so this does not hang
+        w.publish();
+    }
+
+    #[test]
+    fn owned_guard_map() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(7));
+        w.publish();
+        let r = Rc::new(r);
+        let guard = r.enter_owned().unwrap();
+        let guard: OwnedReadGuard<i32, i32> = OwnedReadGuard::map(guard, |t| t);
+        assert_eq!(*guard, 7);
+        assert!(OwnedReadGuard::try_map(guard, |_| None::<&i32>).is_none());
+        assert_eq!(epoch(&r) % 2, 0);
+        let guard = r.enter_owned().unwrap();
+        let guard = OwnedReadGuard::try_map(guard, |t| Some(t)).unwrap();
+        assert_eq!(*guard, 7);
+        assert_eq!(epoch(&r) % 2, 1);
+        drop(guard);
+        assert_eq!(epoch(&r) % 2, 0);
+    }
+
+    #[test]
+    fn owned_guard_map_releases_handle() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(7));
+        w.publish();
+        let factory = r.factory();
+        let readers = factory.reader_count();
+
+        let handle = Rc::new(factory.handle());
+        assert_eq!(factory.reader_count(), readers + 1);
+        let guard = handle.enter_owned().unwrap();
+        let guard = OwnedReadGuard::map(guard, |t| t);
+        let guard = OwnedReadGuard::try_map(guard, |t| Some(t)).unwrap();
+        assert_eq!(Rc::strong_count(&handle), 2);
+        assert!(OwnedReadGuard::try_map(guard, |_| None::<&i32>).is_none());
+        assert_eq!(Rc::strong_count(&handle), 1);
+
+        // the mapped guards were the only other owners, so this releases the reader slot
+        drop(handle);
+        assert_eq!(factory.reader_count(), readers);
+    }
+
+    #[test]
+    fn owned_guard_reentrant() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+        let r = Rc::new(r);
+        let before = epoch(&r);
+
+        let borrowed = r.enter().unwrap();
+        let owned = r.enter_owned().unwrap();
+        let owned2 = r.enter_owned().unwrap();
+        assert_eq!(epoch(&r), before + 1);
+        drop(borrowed);
+        drop(owned2);
+        assert_eq!(epoch(&r), before + 1);
+        drop(owned);
+        assert_eq!(epoch(&r), before + 2);
+        assert_eq!(r.enters.get(), 0);
+    }
+
+    struct VecOp(Vec<i32>);
+
+    impl Absorb<VecOp> for Vec<i32> {
+        fn absorb_first(&mut self, operation: &mut VecOp, _: &Self) {
+            self.extend_from_slice(&operation.0);
+        }
+
+        fn sync_with(&mut self, first: &Self) {
+            self.clone_from(first);
+        }
+    }
+
+    #[test]
+    fn map_split_releases_with_last_guard() {
+        let (mut w, r) = crate::new::<Vec<i32>, _>();
+        w.append(VecOp(vec![1, 2, 3, 4]));
+        w.publish();
+        let before = epoch(&r);
+
+        let (left, right) = ReadGuard::map_split(r.enter().unwrap(), |v| v.split_at(1));
+        assert_eq!(&*left, &[1]);
+        assert_eq!(&*right, &[2, 3, 4]);
+        assert_eq!(r.enters.get(), 2);
+        drop(left);
+        assert_eq!(epoch(&r), before + 1);
+        drop(right);
+        assert_eq!(epoch(&r), before + 2);
+        assert_eq!(r.enters.get(), 0);
+    }
+
+    #[test]
+    fn filter_map_hands_back_original() {
+        let (mut w, r) = crate::new::<Vec<i32>, _>();
+        w.append(VecOp(vec![1, 2]));
+        w.publish();
+        let before = epoch(&r);
+
+        let guard = ReadGuard::filter_map(r.enter().unwrap(), |v| v.get(5)).unwrap_err();
+        assert_eq!(&*guard, &[1, 2]);
+        let guard = ReadGuard::filter_map(guard, |v| v.get(1)).unwrap();
+        assert_eq!(*guard, 2);
+        assert_eq!(r.enters.get(), 1);
+        drop(guard);
+        assert_eq!(epoch(&r), before + 2);
+    }
+
+    #[test]
+    fn zip_and_unzip() {
+        let (mut w, r) = crate::new::<Vec<i32>, _>();
+        w.append(VecOp(vec![1, 2, 3]));
+        w.publish();
+        let before = epoch(&r);
+
+        let first = ReadGuard::map(r.enter().unwrap(), |v| &v[0]);
+        let last = ReadGuard::map(r.enter().unwrap(), |v| &v[2]);
+        assert_eq!(r.enters.get(), 2);
+        let both = ReadGuard::zip(first, last);
+        assert_eq!(r.enters.get(), 1);
+        assert_eq!(both.get(), (&1, &3));
+        assert_eq!((both.left(), both.right()), (&1, &3));
+
+        let (first, last) = both.unzip();
+        assert_eq!(r.enters.get(), 2);
+        drop(last);
+        assert_eq!(epoch(&r), before + 1);
+        assert_eq!(*first, 1);
+        drop(first);
+        assert_eq!(epoch(&r), before + 2);
+    }
+
+    #[test]
+    fn zip_across_publish() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+
+        // a publish in between the two enters does not have to wait for the first guard, since it
+        // only touches the copy that guard is not in.
+        let old = r.enter().unwrap();
+        w.append(CounterAddOp(10));
+        w.publish();
+        let new = r.enter().unwrap();
+        let both = ReadGuard::zip(old, new);
+        assert_eq!(both.get(), (&1, &11));
+    }
+
+    #[test]
+    #[should_panic(expected = "same ReadHandle")]
+    fn zip_different_handles() {
+        let (_w, r) = crate::new::<i32, CounterAddOp>();
+        let r2 = r.clone();
+        let _ = ReadGuard::zip(r.enter().unwrap(), r2.enter().unwrap());
+    }
+
+    #[test]
+    fn sync_handle_one_handle_per_thread() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(42));
+        w.publish();
+        let readers = || r.epochs().registered();
+        assert_eq!(readers(), 2);
+
+        let sync = std::sync::Arc::new(crate::SyncReadHandle::from(r.factory()));
+        assert_eq!(*sync.enter().unwrap(), 42);
+        assert_eq!(*sync.enter().unwrap(), 42);
+        assert_eq!(readers(), 3);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let sync = std::sync::Arc::clone(&sync);
+                std::thread::spawn(move || {
+                    let a = sync.enter().unwrap();
+                    let b = sync.enter().unwrap();
+                    *a + *b
+                })
+            })
+            .collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), 84);
+        }
+        // the threads released their handles as they exited
+        assert_eq!(readers(), 3);
+
+        drop(sync);
+        assert_eq!(readers(), 2);
+
+        drop(w);
+        let sync = crate::SyncReadHandle::from(r.factory());
+        assert!(sync.was_dropped());
+        assert_eq!(
+            sync.try_enter().unwrap_err(),
+            crate::EnterError::WriterDropped
+        );
+    }
+
+    #[test]
+    fn sync_handle_released_lazily_by_other_threads() {
+        let (_w, r) = crate::new::<i32, CounterAddOp>();
+        let epochs = std::sync::Arc::clone(r.epochs());
+        let readers = move || epochs.registered();
+        let sync = std::sync::Arc::new(crate::SyncReadHandle::from(r.factory()));
+
+        let (entered_tx, entered_rx) = std::sync::mpsc::channel();
+        let (dropped_tx, dropped_rx) = std::sync::mpsc::channel();
+        let t = std::thread::spawn({
+            let sync = std::sync::Arc::clone(&sync);
+            let factory = r.factory();
+            let readers = readers.clone();
+            move || {
+                drop(sync.enter());
+                drop(sync);
+                entered_tx.send(()).unwrap();
+                dropped_rx.recv().unwrap();
+                // registering another handle gets rid of the one that can no longer be used
+                let other = crate::SyncReadHandle::from(factory);
+                let _guard = other.enter();
+                readers()
+            }
+        });
+
+        entered_rx.recv().unwrap();
+        assert_eq!(readers(), 3);
+        drop(sync);
+        // the other thread still holds on to its handle
+        assert_eq!(readers(), 3);
+        dropped_tx.send(()).unwrap();
+        assert_eq!(t.join().unwrap(), 3);
+        assert_eq!(readers(), 2);
+    }
+
+    #[test]
+    fn factory_with_local() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+        let readers = || r.epochs().registered();
+
+        let factory = r.factory();
+        let clone = factory.clone();
+        let a = factory.with_local(|r| Rc::new(r.epoch.index()));
+        let b = clone.with_local(|r| Rc::new(r.epoch.index()));
+        assert_eq!(a, b);
+        assert_eq!(readers(), 3);
+
+        // the handle can be entered reentrantly from within the closure
+        let sum = factory.with_local(|r| {
+            let x = r.enter().unwrap();
+            *x + clone.with_local(|r| *r.enter().unwrap())
+        });
+        assert_eq!(sum, 2);
+
+        // a different factory means a different handle
+        let other = r.factory();
+        other.with_local(|_| ());
+        assert_eq!(readers(), 4);
+        drop(other);
+        assert_eq!(readers(), 3);
+
+        drop(factory);
+        assert_eq!(readers(), 3);
+        let sync = crate::SyncReadHandle::from(clone);
+        assert_eq!(*sync.enter().unwrap(), 1);
+        assert_eq!(readers(), 3);
+        drop(sync);
+        assert_eq!(readers(), 2);
+    }
+
+    #[test]
+    fn mapped_handle() {
+        let (mut w, r) = crate::new::<Vec<i32>, _>();
+        w.append(VecOp(vec![1, 2, 3]));
+        w.publish();
+
+        let tail = r.clone().map(|v| &v[1..]);
+        assert_eq!(&*tail.enter().unwrap(), &[2, 3]);
+
+        let factory = tail.factory();
+        let t = std::thread::spawn(move || factory.handle().enter().unwrap().to_vec());
+        assert_eq!(t.join().unwrap(), vec![2, 3]);
+
+        let tail2 = tail.clone();
+        w.append(VecOp(vec![4]));
+        w.publish();
+        assert_eq!(&*tail2.enter().unwrap(), &[2, 3, 4]);
+
+        drop(w);
+        assert!(tail.was_dropped());
+        assert_eq!(
+            tail.try_enter().unwrap_err(),
+            crate::EnterError::WriterDropped
+        );
+    }
+
+    #[test]
+    fn dyn_handles() {
+        use crate::DynReadHandle;
+        let (mut w1, r1) = crate::new::<i32, _>();
+        let (mut w2, r2) = crate::new::<Vec<i32>, _>();
+        w1.append(CounterAddOp(1));
+        w1.publish();
+        w2.append(VecOp(vec![1, 2]));
+        w2.publish();
+        w2.publish();
+
+        let handles: Vec<Box<dyn DynReadHandle>> = vec![
+            Box::new(r1.clone()),
+            Box::new(crate::SyncReadHandle::from(r2.factory())),
+        ];
+        assert_eq!(handles[0].type_name(), "i32");
+        assert_eq!(handles[1].type_name(), std::any::type_name::<Vec<i32>>());
+        assert_eq!(handles[0].generation(), 1);
+        assert_eq!(handles[1].generation(), 2);
+        assert_eq!(handles[0].reader_count(), 3);
+
+        let mut sum = 0;
+        for handle in &handles {
+            handle
+                .enter_dyn(&mut |t| {
+                    if let Some(n) = t.downcast_ref::<i32>() {
+                        sum += n;
+                    } else if let Some(v) = t.downcast_ref::<Vec<i32>>() {
+                        sum += v.iter().sum::<i32>();
+                    }
+                })
+                .unwrap();
+        }
+        assert_eq!(sum, 4);
+        // the sync handle registered a reader for this thread
+        assert_eq!(handles[1].reader_count(), 3);
+
+        drop(w1);
+        assert!(handles[0].was_dropped());
+        assert_eq!(
+            handles[0].enter_dyn(&mut |_| unreachable!()),
+            Err(crate::EnterError::WriterDropped)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use super::{EnterError, ReadHandle, SyncReadHandle};
+use crate::sync::Ordering;
+use std::any::{self, Any};
+
+/// An object-safe view of a read handle, for code that needs to deal with left-right instances
+/// of many different types at once.
+///
+/// This is implemented by [`ReadHandle`] and [`SyncReadHandle`], so a collection of
+/// `Box<dyn DynReadHandle>` can hold handles to instances of any `T`. The data itself is only
+/// available as a [`&dyn Any`](Any), which can be downcast back to the `T` where that is known.
+///
+/// ```
+/// use left_right::{DynReadHandle, ReadHandle};
+///
+/// fn report(handles: &[Box<dyn DynReadHandle>]) {
+///     for handle in handles {
+///         println!(
+///             "{}: generation {}, {} readers",
+///             handle.type_name(),
+///             handle.generation(),
+///             handle.reader_count(),
+///         );
+///         let _ = handle.enter_dyn(&mut |t| {
+///             if let Some(n) = t.downcast_ref::<u64>() {
+///                 println!("  value: {}", n);
+///             }
+///         });
+///     }
+/// }
+/// ```
+pub trait DynReadHandle {
+    /// Returns the name of the type of the data behind this handle.
+    fn type_name(&self) -> &'static str;
+
+    /// Returns true if the [`WriteHandle`](crate::WriteHandle) has been dropped.
+    ///
+    /// See [`ReadHandle::was_dropped`].
+    fn was_dropped(&self) -> bool;
+
+    /// Returns the number of times the writer has published.
+    ///
+    /// See [`ReadHandle::generation`].
+    fn generation(&self) -> usize;
+
+    /// Returns the number of read handles currently registered with the left-right instance,
+    /// including the one the writer holds.
+    fn reader_count(&self) -> usize;
+
+    /// Enter the read copy of the data and call `f` with it.
+    ///
+    /// See [`ReadHandle::try_enter`].
+    fn enter_dyn(&self, f: &mut dyn FnMut(&dyn Any)) -> Result<(), EnterError>;
+}
+
+impl<T: 'static> DynReadHandle for ReadHandle<T> {
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+
+    fn was_dropped(&self) -> bool {
+        ReadHandle::was_dropped(self)
+    }
+
+    fn generation(&self) -> usize {
+        ReadHandle::generation(self)
+    }
+
+    fn reader_count(&self) -> usize {
+        self.epochs().registered()
+    }
+
+    fn enter_dyn(&self, f: &mut dyn FnMut(&dyn Any)) -> Result<(), EnterError> {
+        let guard = self.try_enter()?;
+        f(&*guard);
+        Ok(())
+    }
+}
+
+impl<T: 'static> DynReadHandle for SyncReadHandle<T> {
+    fn type_name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+
+    fn was_dropped(&self) -> bool {
+        SyncReadHandle::was_dropped(self)
+    }
+
+    fn generation(&self) -> usize {
+        self.factory().inner.generation.load(Ordering::Acquire)
+    }
+
+    fn reader_count(&self) -> usize {
+        self.factory().reader_count()
+    }
+
+    fn enter_dyn(&self, f: &mut dyn FnMut(&dyn Any)) -> Result<(), EnterError> {
+        let guard = self.try_enter()?;
+        f(&*guard);
+        Ok(())
+    }
+}
@@ -1,16 +1,22 @@
+use super::local::LocalKey;
 use super::ReadHandle;
-use crate::sync::{Arc, AtomicPtr};
+use crate::shared::Shared;
+use crate::sync::{Arc, Ordering};
+use crate::{Absorb, WriteHandle};
 use std::fmt;
 
 /// A type that is both `Sync` and `Send` and lets you produce new [`ReadHandle`] instances.
 ///
 /// This serves as a handy way to distribute read handles across many threads without requiring
 /// additional external locking to synchronize access to the non-`Sync` [`ReadHandle`] type. Note
-/// that this _internally_ takes a lock whenever you call [`ReadHandleFactory::handle`], so
-/// you should not expect producing new handles rapidly to scale well.
+/// that [`ReadHandleFactory::handle`] has to find a free slot among those of all other readers, so
+/// you should not expect producing new handles rapidly to scale well. If you need a handle for a
+/// short while on a thread that may need one again later, use
+/// [`ReadHandleFactory::with_local`] instead.
 pub struct ReadHandleFactory<T> {
-    pub(super) inner: Arc<AtomicPtr<T>>,
+    pub(super) inner: Arc<Shared<T>>,
     pub(super) epochs: crate::Epochs,
+    pub(super) local: LocalKey,
 }
 
 impl<T> fmt::Debug for ReadHandleFactory<T> {
@@ -26,6 +32,7 @@ impl<T> Clone for ReadHandleFactory<T> {
         Self {
             inner: Arc::clone(&self.inner),
             epochs: Arc::clone(&self.epochs),
+            local: self.local.clone(),
         }
     }
 }
@@ -36,4 +43,105 @@ impl<T> ReadHandleFactory<T> {
     pub fn handle(&self) -> ReadHandle<T> {
         ReadHandle::new_with_arc(Arc::clone(&self.inner), Arc::clone(&self.epochs))
     }
+
+    /// Returns the number of [`ReadHandle`]s currently registered with the left-right instance.
+    ///
+    /// This includes the handle the [`WriteHandle`] keeps for itself, as well as all
+    /// [thread-local](Self::with_local) handles that have not been released yet.
+    pub fn reader_count(&self) -> usize {
+        self.epochs.registered()
+    }
+
+    /// Run `f` with this thread's [`ReadHandle`] from this factory.
+    ///
+    /// The first time a thread calls this, a new handle is created just like with
+    /// [`handle`](Self::handle). After that, the thread keeps reusing the same handle, so this
+    /// only costs a lookup in a thread-local map. The handle is shared with all clones of this factory, and with any
+    /// [`SyncReadHandle`](super::SyncReadHandle) made from them.
+    ///
+    /// A thread's handle is deregistered when the thread exits. Once this factory and all its
+    /// clones have been dropped, the thread that dropped the last of them deregisters its handle
+    /// right away, and other threads do so the next time they register a new thread-local handle
+    /// (from any factory), or when they exit.
+    ///
+    /// ```
+    /// use left_right::ReadHandleFactory;
+    ///
+    /// // cheap to call on any thread, as often as you like
+    /// fn get(factory: &ReadHandleFactory<i32>) -> Option<i32> {
+    ///     factory.with_local(|r| r.enter().map(|x| *x))
+    /// }
+    /// ```
+    pub fn with_local<F, R>(&self, f: F) -> R
+    where
+        T: 'static,
+        F: FnOnce(&ReadHandle<T>) -> R,
+    {
+        f(&self.local())
+    }
+
+    pub(super) fn local(&self) -> std::rc::Rc<ReadHandle<T>>
+    where
+        T: 'static,
+    {
+        self.local.get(self)
+    }
+}
+
+impl<T> ReadHandleFactory<T> {
+    /// Attach a new [`WriteHandle`] to a left-right instance that no longer has one.
+    ///
+    /// This is possible if the previous writer was [frozen](WriteHandle::freeze), or if it was
+    /// orphaned because it was dropped while its thread was panicking and it was set to
+    /// [freeze on panic](WriteHandle::freeze_on_panic). In both cases, the readers
+    /// still have access to the copy of the data that was last published, and keep reading it
+    /// through their existing handles. The new writer starts out from a clone of that copy, and
+    /// from the [high-water mark](ReadHandle::high_water_mark) readers see, so
+    /// [`append_seq`](WriteHandle::append_seq) keeps rejecting what was already published.
+    ///
+    /// If the previous writer was orphaned because an [`Absorb`] method panicked, the clone is
+    /// taken from the last successfully published copy, so the instance is no longer
+    /// [poisoned](ReadHandle::is_poisoned) after this.
+    ///
+    /// Returns `None` if the instance still has a writer, if the data has been dropped along with
+    /// its writer, or if some other thread has already taken over. Thus, there is never more than
+    /// one writer at a time.
+    ///
+    /// The data must be `Send` and `Sync`, since the new writer clones the read copy while readers
+    /// on other threads may be reading it, and eventually drops it.
+    pub fn take_writer<O>(&self) -> Option<WriteHandle<T, O>>
+    where
+        T: Absorb<O> + Clone + Send + Sync,
+    {
+        if self
+            .inner
+            .frozen
+            .compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return None;
+        }
+
+        // the copy is ours again, so readers must no longer drop it when they go away.
+        let drop_frozen = self.inner.drop_frozen.lock().unwrap().take();
+        assert!(drop_frozen.is_some(), "frozen instance left no data behind");
+
+        // safety: the copy is no longer written to, and we will not free it while we hold this
+        // reference.
+        let r_handle = unsafe {
+            self.inner
+                .ptr
+                .load(Ordering::Acquire)
+                .as_ref()
+                .expect("frozen instance left no data behind")
+        };
+        let w_handle = r_handle.clone();
+        self.inner.poisoned.store(false, Ordering::Release);
+
+        Some(WriteHandle::new(
+            w_handle,
+            Arc::clone(&self.epochs),
+            self.handle(),
+        ))
+    }
 }
@@ -1,6 +1,9 @@
 use crate::sync::{AtomicUsize, Ordering};
 use std::cell::Cell;
+use std::fmt;
 use std::mem;
+use std::ptr::NonNull;
+use std::rc::Rc;
 
 #[derive(Debug, Copy, Clone)]
 pub(super) struct ReadHandleState<'rh> {
@@ -8,10 +11,22 @@ pub(super) struct ReadHandleState<'rh> {
     pub(super) enters: &'rh Cell<usize>,
 }
 
+impl ReadHandleState<'_> {
+    /// Called whenever a guard is dropped.
+    fn exit(&self) {
+        let enters = self.enters.get() - 1;
+        self.enters.set(enters);
+        if enters == 0 {
+            // We are the last guard to be dropped -- now release our epoch.
+            self.epoch.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
 impl<'rh, T> From<&'rh super::ReadHandle<T>> for ReadHandleState<'rh> {
     fn from(rh: &'rh super::ReadHandle<T>) -> Self {
         Self {
-            epoch: &rh.epoch,
+            epoch: rh.epoch.epoch(),
             enters: &rh.enters,
         }
     }
@@ -23,8 +38,10 @@ impl<'rh, T> From<&'rh super::ReadHandle<T>> for ReadHandleState<'rh> {
 /// [`WriteHandle::publish`](crate::WriteHandle::publish), that call will block until this guard is
 /// dropped.
 ///
-/// To scope the guard to a subset of the data in `T`, use [`map`](Self::map) and
-/// [`try_map`](Self::try_map).
+/// To scope the guard to a subset of the data in `T`, use [`map`](Self::map),
+/// [`try_map`](Self::try_map), and [`filter_map`](Self::filter_map). To split it into guards for
+/// disjoint parts of the data, use [`map_split`](Self::map_split), and to combine two guards into
+/// one, use [`zip`](Self::zip).
 #[derive(Debug)]
 pub struct ReadGuard<'rh, T: ?Sized> {
     // NOTE: _technically_ this is more like &'self.
@@ -99,6 +116,131 @@ impl<'rh, T: ?Sized> ReadGuard<'rh, T> {
         mem::forget(orig);
         Some(rg)
     }
+
+    /// Makes a new `ReadGuard` for a component of the borrowed data that may not exist, or
+    /// returns the original guard if it does not.
+    ///
+    /// This method differs from [`try_map`](Self::try_map) in that the original guard is handed
+    /// back if the closure maps to `None`, like [`Ref::filter_map`](std::cell::Ref::filter_map).
+    ///
+    /// This is an associated function that needs to be used as `ReadGuard::filter_map(...)`,
+    /// since a method would interfere with methods of the same name on the contents of a
+    /// `Readguard` used through `Deref`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use left_right::{ReadGuard, ReadHandle};
+    ///
+    /// fn first_or_all(handle: &ReadHandle<Vec<i32>>) -> Option<ReadGuard<'_, [i32]>> {
+    ///     handle.enter().map(|guard| {
+    ///         match ReadGuard::filter_map(guard, |t| t.first()) {
+    ///             Ok(first) => ReadGuard::map(first, std::slice::from_ref),
+    ///             Err(all) => ReadGuard::map(all, |t| &t[..]),
+    ///         }
+    ///     })
+    /// }
+    /// ```
+    pub fn filter_map<F, U: ?Sized>(orig: Self, f: F) -> Result<ReadGuard<'rh, U>, Self>
+    where
+        F: for<'a> FnOnce(&'a T) -> Option<&'a U>,
+    {
+        match f(orig.t) {
+            Some(t) => {
+                let rg = ReadGuard {
+                    t,
+                    handle: orig.handle,
+                };
+                mem::forget(orig);
+                Ok(rg)
+            }
+            None => Err(orig),
+        }
+    }
+
+    /// Splits a `ReadGuard` into multiple `ReadGuard`s for different components of the borrowed
+    /// data.
+    ///
+    /// The two guards count as separate [`enter`](super::ReadHandle::enter)s, so the writer
+    /// remains blocked until both of them have been dropped.
+    ///
+    /// This is an associated function that needs to be used as `ReadGuard::map_split(...)`, since
+    /// a method would interfere with methods of the same name on the contents of a `Readguard`
+    /// used through `Deref`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use left_right::{ReadGuard, ReadHandle};
+    ///
+    /// fn halves(handle: &ReadHandle<Vec<i32>>) -> Option<(ReadGuard<'_, [i32]>, ReadGuard<'_, [i32]>)> {
+    ///     handle.enter().map(|guard| {
+    ///         ReadGuard::map_split(guard, |t| t.split_at(t.len() / 2))
+    ///     })
+    /// }
+    /// ```
+    pub fn map_split<F, U: ?Sized, V: ?Sized>(
+        orig: Self,
+        f: F,
+    ) -> (ReadGuard<'rh, U>, ReadGuard<'rh, V>)
+    where
+        F: for<'a> FnOnce(&'a T) -> (&'a U, &'a V),
+    {
+        let (u, v) = f(orig.t);
+        let handle = orig.handle;
+        mem::forget(orig);
+        // we're handing out one more guard than we were given.
+        handle.enters.set(handle.enters.get() + 1);
+        (ReadGuard { t: u, handle }, ReadGuard { t: v, handle })
+    }
+
+    /// Combines two `ReadGuard`s taken out through the same [`ReadHandle`] into one.
+    ///
+    /// The two guards may come from different publishes: a reentrant call to
+    /// [`enter`](super::ReadHandle::enter) sees whatever the writer published last, even while an
+    /// earlier guard from the same handle still holds on to an older copy. Both copies stay alive
+    /// and unchanged for as long as the resulting [`ZippedReadGuard`] does, but if the references
+    /// have to agree with one another, take them both from a single guard instead (see
+    /// [`map_split`](Self::map_split)).
+    ///
+    /// This is an associated function that needs to be used as `ReadGuard::zip(...)`, since
+    /// a method would interfere with methods of the same name on the contents of a `Readguard`
+    /// used through `Deref`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the two guards were not taken out through the same `ReadHandle`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use left_right::{ReadGuard, ReadHandle};
+    ///
+    /// fn first_and_last(handle: &ReadHandle<Vec<i32>>) -> Option<(i32, i32)> {
+    ///     let first = ReadGuard::try_map(handle.enter()?, |t| t.first())?;
+    ///     let last = ReadGuard::try_map(handle.enter()?, |t| t.last())?;
+    ///     let both = ReadGuard::zip(first, last);
+    ///     let (first, last) = both.get();
+    ///     Some((*first, *last))
+    /// }
+    /// ```
+    ///
+    /// [`ReadHandle`]: super::ReadHandle
+    pub fn zip<U: ?Sized>(orig: Self, other: ReadGuard<'rh, U>) -> ZippedReadGuard<'rh, T, U> {
+        assert!(
+            std::ptr::eq(orig.handle.epoch, other.handle.epoch),
+            "can only zip guards taken out through the same ReadHandle"
+        );
+        let zg = ZippedReadGuard {
+            t: orig.t,
+            u: other.t,
+            handle: orig.handle,
+        };
+        mem::forget(orig);
+        // the zipped guard only counts as a single enter.
+        drop(other);
+        zg
+    }
 }
 
 impl<'rh, T: ?Sized> AsRef<T> for ReadGuard<'rh, T> {
@@ -116,11 +258,158 @@ impl<'rh, T: ?Sized> std::ops::Deref for ReadGuard<'rh, T> {
 
 impl<'rh, T: ?Sized> Drop for ReadGuard<'rh, T> {
     fn drop(&mut self) {
-        let enters = self.handle.enters.get() - 1;
-        self.handle.enters.set(enters);
-        if enters == 0 {
-            // We are the last guard to be dropped -- now release our epoch.
-            self.handle.epoch.fetch_add(1, Ordering::AcqRel);
-        }
+        self.handle.exit();
+    }
+}
+
+/// A guard wrapping two live references into a left-right protected data structure.
+///
+/// Created by [`ReadGuard::zip`]. The references may point into different published versions of
+/// the data, if the writer published in between taking out the two zipped guards. Either way, the
+/// writer remains blocked until this guard is dropped.
+#[derive(Debug)]
+pub struct ZippedReadGuard<'rh, T: ?Sized, U: ?Sized> {
+    t: &'rh T,
+    u: &'rh U,
+    handle: ReadHandleState<'rh>,
+}
+
+impl<'rh, T: ?Sized, U: ?Sized> ZippedReadGuard<'rh, T, U> {
+    /// Returns both references.
+    pub fn get(&self) -> (&T, &U) {
+        (self.t, self.u)
+    }
+
+    /// Returns the reference taken from the first of the zipped guards.
+    pub fn left(&self) -> &T {
+        self.t
+    }
+
+    /// Returns the reference taken from the second of the zipped guards.
+    pub fn right(&self) -> &U {
+        self.u
+    }
+
+    /// Splits the zipped guard back up into two separate `ReadGuard`s.
+    pub fn unzip(self) -> (ReadGuard<'rh, T>, ReadGuard<'rh, U>) {
+        let handle = self.handle;
+        let (t, u) = (self.t, self.u);
+        mem::forget(self);
+        // we're handing out one more guard than we were given.
+        handle.enters.set(handle.enters.get() + 1);
+        (ReadGuard { t, handle }, ReadGuard { t: u, handle })
+    }
+}
+
+impl<'rh, T: ?Sized, U: ?Sized> Drop for ZippedReadGuard<'rh, T, U> {
+    fn drop(&mut self) {
+        self.handle.exit();
+    }
+}
+
+/// A guard wrapping a live reference into a left-right protected `T` that owns its
+/// [`ReadHandle`](super::ReadHandle).
+///
+/// This is the owned counterpart to [`ReadGuard`]: rather than borrowing the handle it was created
+/// from, it keeps the handle alive through an `Rc`, and is thus `'static` as long as `T` is. It
+/// can be returned from a function that creates a temporary handle, or stored in a struct next to
+/// its handle. Like a `ReadGuard`, it holds up [`WriteHandle::publish`](crate::WriteHandle::publish)
+/// for as long as it lives.
+///
+/// Create one using [`ReadHandle::enter_owned`](super::ReadHandle::enter_owned). To scope the guard
+/// to a subset of the data in `T`, use [`map`](Self::map) and [`try_map`](Self::try_map); the
+/// second type parameter is the type of that subset.
+pub struct OwnedReadGuard<T, U: ?Sized = T> {
+    // NOTE: the pointer is valid until the guard is dropped, just like the reference in ReadGuard.
+    pub(super) t: NonNull<U>,
+    pub(super) handle: Rc<super::ReadHandle<T>>,
+}
+
+impl<T, U: ?Sized> OwnedReadGuard<T, U> {
+    /// Take the handle out of the guard without leaving the epoch, so that a new guard can take
+    /// over the enter.
+    fn into_handle(orig: Self) -> Rc<super::ReadHandle<T>> {
+        let orig = mem::ManuallyDrop::new(orig);
+        // safety: `orig` is never dropped, so the handle is moved out rather than duplicated.
+        unsafe { std::ptr::read(&orig.handle) }
+    }
+
+    /// Makes a new `OwnedReadGuard` for a component of the borrowed data.
+    ///
+    /// This is an associated function that needs to be used as `OwnedReadGuard::map(...)`, since
+    /// a method would interfere with methods of the same name on the contents of an
+    /// `OwnedReadGuard` used through `Deref`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use left_right::{OwnedReadGuard, ReadHandleFactory};
+    ///
+    /// fn get_str(
+    ///     factory: &ReadHandleFactory<Vec<(String, i32)>>,
+    ///     i: usize,
+    /// ) -> Option<OwnedReadGuard<Vec<(String, i32)>, str>> {
+    ///     let handle = std::rc::Rc::new(factory.handle());
+    ///     handle.enter_owned().map(|guard| {
+    ///         OwnedReadGuard::map(guard, |t| {
+    ///             &*t[i].0
+    ///         })
+    ///     })
+    /// }
+    /// ```
+    pub fn map<F, V: ?Sized>(orig: Self, f: F) -> OwnedReadGuard<T, V>
+    where
+        F: for<'a> FnOnce(&'a U) -> &'a V,
+    {
+        let t = NonNull::from(f(&*orig));
+        let handle = Self::into_handle(orig);
+        OwnedReadGuard { t, handle }
+    }
+
+    /// Makes a new `OwnedReadGuard` for a component of the borrowed data that may not exist.
+    ///
+    /// This method differs from [`map`](Self::map) in that it drops the guard if the closure maps
+    /// to `None`. This allows you to "lift" an `OwnedReadGuard<T, Option<U>>` into an
+    /// `Option<OwnedReadGuard<T, U>>`.
+    ///
+    /// This is an associated function that needs to be used as `OwnedReadGuard::try_map(...)`,
+    /// since a method would interfere with methods of the same name on the contents of an
+    /// `OwnedReadGuard` used through `Deref`.
+    pub fn try_map<F, V: ?Sized>(orig: Self, f: F) -> Option<OwnedReadGuard<T, V>>
+    where
+        F: for<'a> FnOnce(&'a U) -> Option<&'a V>,
+    {
+        let t = NonNull::from(f(&*orig)?);
+        let handle = Self::into_handle(orig);
+        Some(OwnedReadGuard { t, handle })
+    }
+}
+
+impl<T, U: ?Sized + fmt::Debug> fmt::Debug for OwnedReadGuard<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedReadGuard")
+            .field("t", &&**self)
+            .finish()
+    }
+}
+
+impl<T, U: ?Sized> AsRef<U> for OwnedReadGuard<T, U> {
+    fn as_ref(&self) -> &U {
+        self
+    }
+}
+
+impl<T, U: ?Sized> std::ops::Deref for OwnedReadGuard<T, U> {
+    type Target = U;
+    fn deref(&self) -> &Self::Target {
+        // safety: the epoch of our handle stays odd until we are dropped, so the copy this
+        // pointer leads into stays alive and unmodified until then.
+        unsafe { self.t.as_ref() }
+    }
+}
+
+impl<T, U: ?Sized> Drop for OwnedReadGuard<T, U> {
+    fn drop(&mut self) {
+        ReadHandleState::from(&*self.handle).exit();
     }
 }
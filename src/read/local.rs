@@ -0,0 +1,185 @@
+use super::{EnterError, OwnedReadGuard, ReadHandle, ReadHandleFactory};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+// These are deliberately the std primitives rather than the ones in `crate::sync`: they only keep
+// track of which thread has which handle, and play no part in the synchronization with the writer.
+static NEXT_KEY: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static HANDLES: RefCell<HashMap<usize, Entry>> = RefCell::new(HashMap::new());
+}
+
+struct Entry {
+    /// Dead once everything that could look up this entry again is gone.
+    owner: Weak<()>,
+    /// An `Rc<ReadHandle<T>>`, for whatever `T` the owner has.
+    handle: Rc<dyn Any>,
+}
+
+/// Identifies one set of per-thread [`ReadHandle`]s in the thread-local handle cache.
+///
+/// Every thread that asks for a handle through a given key gets its own, which it keeps reusing
+/// until the thread exits. Clones of a key share the same handles. Once the last clone of a key is
+/// dropped, the thread that dropped it releases its handle right away, and other threads release
+/// theirs the next time they register a new handle, or when they exit, whichever comes first.
+#[derive(Clone)]
+pub(super) struct LocalKey {
+    id: usize,
+    owner: Arc<()>,
+}
+
+impl LocalKey {
+    pub(super) fn new() -> Self {
+        Self {
+            id: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+            owner: Arc::new(()),
+        }
+    }
+
+    /// Returns this thread's handle for this key, creating one through `factory` if needed.
+    ///
+    /// `factory` must produce handles to the same instance every time it is called with a given
+    /// key.
+    pub(super) fn get<T: 'static>(&self, factory: &ReadHandleFactory<T>) -> Rc<ReadHandle<T>> {
+        let cached = HANDLES.try_with(|handles| {
+            let mut handles = handles.borrow_mut();
+            if let Some(entry) = handles.get(&self.id) {
+                return (Rc::clone(&entry.handle), Vec::new());
+            }
+
+            // we're about to register a new handle anyway, so this is a good time to get rid of
+            // any handles that can no longer be looked up.
+            let dead: Vec<_> = handles
+                .iter()
+                .filter(|(_, entry)| entry.owner.upgrade().is_none())
+                .map(|(&id, _)| id)
+                .collect();
+            let dead: Vec<_> = dead
+                .into_iter()
+                .filter_map(|id| handles.remove(&id))
+                .collect();
+
+            let handle: Rc<dyn Any> = Rc::new(factory.handle());
+            handles.insert(
+                self.id,
+                Entry {
+                    owner: Arc::downgrade(&self.owner),
+                    handle: Rc::clone(&handle),
+                },
+            );
+            (handle, dead)
+        });
+
+        match cached {
+            Ok((handle, dead)) => {
+                // dropping a handle may drop the data, which may in turn use the cache, so only do
+                // so once we no longer hold on to it.
+                drop(dead);
+                handle
+                    .downcast()
+                    .expect("handle cache entry has the wrong type")
+            }
+            // this thread is exiting and its cache is already gone, so make do without.
+            Err(_) => Rc::new(factory.handle()),
+        }
+    }
+}
+
+impl Drop for LocalKey {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.owner) != 1 {
+            // other clones may still use this thread's handle.
+            return;
+        }
+
+        let entry = HANDLES
+            .try_with(|handles| handles.borrow_mut().remove(&self.id))
+            .ok()
+            .flatten();
+        drop(entry);
+    }
+}
+
+/// A read handle that is `Sync`, and so can be used from many threads at once.
+///
+/// [`ReadHandle`] is `!Sync` to force every reader to have a handle of its own, which is what
+/// makes reads scale across cores. A `SyncReadHandle` keeps that property while being shareable:
+/// behind the scenes, every thread that reads through it gets a `ReadHandle` of its own, which is
+/// registered with the writer the first time the thread calls [`enter`](Self::enter). After that,
+/// entering is wait-free again, and only costs a lookup in a thread-local map.
+///
+/// The per-thread handles are the same ones that [`ReadHandleFactory::with_local`] uses, so they
+/// are shared with the factory the `SyncReadHandle` was created from, as well as with all its
+/// clones. A thread's handle is deregistered when the thread exits. Once the last of those is
+/// dropped, the thread that dropped it deregisters its handle right away; other threads release
+/// theirs the next time they register a new handle, or when they exit.
+///
+/// Since guards are tied to the thread-local handle they were taken out through, the guards
+/// returned by `enter` are [`OwnedReadGuard`]s, and cannot be sent to other threads.
+///
+/// ```
+/// use left_right::SyncReadHandle;
+///
+/// fn is_sync<T: Send + Sync>() {}
+///
+/// is_sync::<SyncReadHandle<u64>>()
+/// ```
+pub struct SyncReadHandle<T> {
+    factory: ReadHandleFactory<T>,
+}
+
+impl<T> fmt::Debug for SyncReadHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncReadHandle")
+            .field("factory", &self.factory)
+            .finish()
+    }
+}
+
+impl<T> Clone for SyncReadHandle<T> {
+    fn clone(&self) -> Self {
+        Self::from(self.factory.clone())
+    }
+}
+
+impl<T> From<ReadHandleFactory<T>> for SyncReadHandle<T> {
+    fn from(factory: ReadHandleFactory<T>) -> Self {
+        Self { factory }
+    }
+}
+
+impl<T: 'static> SyncReadHandle<T> {
+    /// Take out a guarded live reference to the read copy of the `T` through this thread's
+    /// handle.
+    ///
+    /// See [`ReadHandle::enter`].
+    pub fn enter(&self) -> Option<OwnedReadGuard<T>> {
+        self.try_enter().ok()
+    }
+
+    /// Take out a guarded live reference to the read copy of the `T` through this thread's
+    /// handle, or learn why that is not possible.
+    ///
+    /// See [`ReadHandle::try_enter`].
+    pub fn try_enter(&self) -> Result<OwnedReadGuard<T>, EnterError> {
+        self.factory.local().try_enter_owned()
+    }
+}
+
+impl<T> SyncReadHandle<T> {
+    /// Returns the [`ReadHandleFactory`] this handle produces its per-thread handles with.
+    pub fn factory(&self) -> &ReadHandleFactory<T> {
+        &self.factory
+    }
+
+    /// Returns true if the [`WriteHandle`](crate::WriteHandle) has been dropped.
+    pub fn was_dropped(&self) -> bool {
+        self.factory.inner.ptr.load(Ordering::Acquire).is_null()
+    }
+}
@@ -0,0 +1,126 @@
+use super::{EnterError, ReadGuard, ReadHandle, ReadHandleFactory};
+use std::fmt;
+
+/// A read handle that only gives access to a part of a left-right protected `T`.
+///
+/// Created by [`ReadHandle::map`]. Every guard taken out through a `MappedReadHandle` goes
+/// through the same projection, so code that holds one can only ever see the `U` it exposes, not
+/// the rest of the `T`. This makes it a handy way to hand out restricted read access.
+///
+/// ```
+/// use left_right::{MappedReadHandle, ReadHandle};
+///
+/// struct State {
+///     config: String,
+///     secrets: Vec<u8>,
+/// }
+///
+/// fn for_plugin(handle: ReadHandle<State>) -> MappedReadHandle<State, str> {
+///     handle.map(|state| &state.config[..])
+/// }
+/// ```
+///
+/// Otherwise, it behaves just like a `ReadHandle`: it can be cloned, and a
+/// [`MappedReadHandleFactory`] can produce more of them from any thread.
+pub struct MappedReadHandle<T, U: ?Sized> {
+    handle: ReadHandle<T>,
+    map: fn(&T) -> &U,
+}
+
+impl<T, U: ?Sized> fmt::Debug for MappedReadHandle<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedReadHandle")
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+impl<T, U: ?Sized> Clone for MappedReadHandle<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            map: self.map,
+        }
+    }
+}
+
+impl<T> ReadHandle<T> {
+    /// Turn this handle into one that only gives access to the part of the `T` that `map`
+    /// returns.
+    ///
+    /// See [`MappedReadHandle`].
+    pub fn map<U: ?Sized>(self, map: fn(&T) -> &U) -> MappedReadHandle<T, U> {
+        MappedReadHandle { handle: self, map }
+    }
+}
+
+impl<T, U: ?Sized> MappedReadHandle<T, U> {
+    /// Take out a guarded live reference to the projected part of the read copy of the `T`.
+    ///
+    /// See [`ReadHandle::enter`].
+    pub fn enter(&self) -> Option<ReadGuard<'_, U>> {
+        self.try_enter().ok()
+    }
+
+    /// Take out a guarded live reference to the projected part of the read copy of the `T`, or
+    /// learn why that is not possible.
+    ///
+    /// See [`ReadHandle::try_enter`].
+    pub fn try_enter(&self) -> Result<ReadGuard<'_, U>, EnterError> {
+        let map = self.map;
+        self.handle
+            .try_enter()
+            .map(|guard| ReadGuard::map(guard, map))
+    }
+
+    /// Returns true if the [`WriteHandle`](crate::WriteHandle) has been dropped.
+    pub fn was_dropped(&self) -> bool {
+        self.handle.was_dropped()
+    }
+
+    /// Create a [`MappedReadHandleFactory`] which is `Send` & `Sync` and can be shared across
+    /// threads to create additional `MappedReadHandle` instances with the same projection.
+    pub fn factory(&self) -> MappedReadHandleFactory<T, U> {
+        MappedReadHandleFactory {
+            factory: self.handle.factory(),
+            map: self.map,
+        }
+    }
+}
+
+/// A type that is both `Sync` and `Send` and lets you produce new [`MappedReadHandle`] instances.
+///
+/// Like a [`ReadHandleFactory`], except that all handles it produces only give access to the
+/// part of the data that the [`MappedReadHandle`] it was created from did.
+pub struct MappedReadHandleFactory<T, U: ?Sized> {
+    factory: ReadHandleFactory<T>,
+    map: fn(&T) -> &U,
+}
+
+impl<T, U: ?Sized> fmt::Debug for MappedReadHandleFactory<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedReadHandleFactory")
+            .field("factory", &self.factory)
+            .finish()
+    }
+}
+
+impl<T, U: ?Sized> Clone for MappedReadHandleFactory<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            factory: self.factory.clone(),
+            map: self.map,
+        }
+    }
+}
+
+impl<T, U: ?Sized> MappedReadHandleFactory<T, U> {
+    /// Produce a new [`MappedReadHandle`] to the same left-right data structure, with the same
+    /// projection, as this factory was originally produced from.
+    pub fn handle(&self) -> MappedReadHandle<T, U> {
+        MappedReadHandle {
+            handle: self.factory.handle(),
+            map: self.map,
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// An owned, immutable copy of a left-right protected `T`.
+///
+/// Unlike a [`ReadGuard`](crate::ReadGuard), a `Snapshot` is entirely detached from the
+/// left-right instance it was taken from. Holding on to it does not stop the writer from calling
+/// [`WriteHandle::publish`](crate::WriteHandle::publish), so it is the right tool for long-running
+/// reads that do not need to observe new writes.
+///
+/// Snapshots are reference counted, so cloning one is cheap, and readers that ask for a snapshot
+/// of the same published version share the same copy. See [`ReadHandle::snapshot`] for how they
+/// are created.
+///
+/// [`ReadHandle::snapshot`]: crate::ReadHandle::snapshot
+pub struct Snapshot<T> {
+    pub(super) t: Arc<T>,
+}
+
+impl<T> Snapshot<T> {
+    /// Returns true if both snapshots share the same copy of the data.
+    ///
+    /// This is an associated function that needs to be used as `Snapshot::ptr_eq(...)`, since a
+    /// method would interfere with methods of the same name on the contents of a `Snapshot` used
+    /// through `Deref`.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.t, &other.t)
+    }
+}
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self {
+            t: Arc::clone(&self.t),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Snapshot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Snapshot").field(&*self.t).finish()
+    }
+}
+
+impl<T> AsRef<T> for Snapshot<T> {
+    fn as_ref(&self) -> &T {
+        &self.t
+    }
+}
+
+impl<T> Deref for Snapshot<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.t
+    }
+}
+
+/// The error returned by [`ReadHandle::enter_at`] when the requested version of the data is not
+/// available.
+///
+/// [`ReadHandle::enter_at`]: crate::ReadHandle::enter_at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VersionGone {
+    pub(crate) oldest: Option<usize>,
+}
+
+impl VersionGone {
+    /// The generation of the oldest version that is still retained, if any.
+    pub fn oldest_retained(&self) -> Option<usize> {
+        self.oldest
+    }
+}
+
+impl fmt::Display for VersionGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.oldest {
+            Some(oldest) => write!(
+                f,
+                "the requested version is not retained; the oldest one is generation {}",
+                oldest
+            ),
+            None => f.write_str("no versions of the left-right instance are retained"),
+        }
+    }
+}
+
+impl std::error::Error for VersionGone {}
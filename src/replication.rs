@@ -0,0 +1,491 @@
+//! Replicating a left-right instance to followers, for example in other processes.
+//!
+//! The leader side is a [publish hook](WriteHandle::on_publish) set up by
+//! [`WriteHandle::replicate_to`] (or [`WriteHandle::replicate_with`]) that encodes the operations
+//! of every publish, along with its generation, as a checksummed frame. The frames go out over any
+//! [`Write`], such as a pipe or a socket. On the other end, a [`Follower`] decodes the frames and
+//! applies them to a [`WriteHandle`] of its own, so that its readers see the same versions of the
+//! data as the leader's readers, under the same [generations](crate::ReadHandle::generation).
+//!
+//! How operations are turned into bytes is up to an [`OpCodec`]. Frames use the same format as the
+//! [write-ahead log](crate::persist), if that is enabled as well.
+//!
+//! A follower must start out with the same data as the leader's readers see when the leader
+//! starts replicating. The easiest way to get there is to start both out empty:
+//!
+//! ```
+//! use left_right::replication::{Follower, OpCodec};
+//! use left_right::Absorb;
+//! use std::io;
+//!
+//! struct CounterAddOp(i32);
+//! impl Absorb<CounterAddOp> for i32 {
+//!     fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+//!         *self += operation.0;
+//!     }
+//!     fn sync_with(&mut self, first: &Self) {
+//!         *self = *first
+//!     }
+//! }
+//!
+//! struct Codec;
+//! impl OpCodec<CounterAddOp> for Codec {
+//!     fn encode(&self, operation: &CounterAddOp, buf: &mut Vec<u8>) {
+//!         buf.extend_from_slice(&operation.0.to_le_bytes());
+//!     }
+//!     fn decode(&self, bytes: &[u8]) -> io::Result<CounterAddOp> {
+//!         let mut n = [0; 4];
+//!         n.copy_from_slice(bytes);
+//!         Ok(CounterAddOp(i32::from_le_bytes(n)))
+//!     }
+//! }
+//!
+//! # #[cfg(unix)]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let (to_follower, mut from_leader) = std::os::unix::net::UnixStream::pair()?;
+//! let (mut leader, leader_reads) = left_right::new::<i32, CounterAddOp>();
+//! leader.publish().replicate_to(Codec, to_follower);
+//!
+//! let (follower, follower_reads) = left_right::new::<i32, CounterAddOp>();
+//! let mut follower = Follower::new(follower, Codec);
+//!
+//! leader.append(CounterAddOp(1)).publish();
+//! follower.receive(&mut from_leader)?;
+//! assert_eq!(*follower_reads.enter().unwrap(), 1);
+//! assert_eq!(follower_reads.generation(), leader_reads.generation());
+//! # Ok(())
+//! # }
+//! # #[cfg(not(unix))]
+//! # fn main() {}
+//! ```
+
+pub use crate::frame::OpCodec;
+use crate::frame::{self, Frame};
+use crate::{Absorb, WriteHandle};
+use std::fmt;
+use std::io::{self, Read, Write};
+// Like the change feed, this is fed by a publish hook, and plays no part in the synchronization
+// between readers and the writer, so the std primitives are fine.
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Reports whether replication set up by [`WriteHandle::replicate_with`] is still going, and why
+/// it stopped if it is not.
+///
+/// It is cheap to clone, and can be checked from any thread.
+#[derive(Debug, Clone)]
+pub struct ReplicationStatus {
+    inner: Arc<Mutex<Status>>,
+}
+
+#[derive(Debug, Default)]
+struct Status {
+    stopped: bool,
+    error: Option<io::Error>,
+}
+
+impl ReplicationStatus {
+    fn status(&self) -> MutexGuard<'_, Status> {
+        // the lock is never held across anything that can panic half-way through an update.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns true if sending a frame failed, and replication has stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.status().stopped
+    }
+
+    /// Returns the error that sending a frame failed with, if replication has stopped.
+    ///
+    /// The error is only returned once, to the first caller. [`is_stopped`](Self::is_stopped)
+    /// keeps returning true after that.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.status().error.take()
+    }
+}
+
+impl<T, O> WriteHandle<T, O>
+where
+    T: Absorb<O>,
+    O: 'static,
+{
+    /// Replicate the operations of every publish to a [`Follower`] on the other end of `out`.
+    ///
+    /// Every publish writes one frame to `out`, and then flushes it. See
+    /// [`replicate_with`](Self::replicate_with) for the details.
+    pub fn replicate_to<C, W>(&mut self, codec: C, mut out: W) -> ReplicationStatus
+    where
+        C: OpCodec<O> + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        self.replicate_with(codec, move |frame| {
+            out.write_all(frame)?;
+            out.flush()
+        })
+    }
+
+    /// Replicate the operations of every publish by passing them to `send` as an encoded frame.
+    ///
+    /// This is the way to replicate over something other than a [`Write`], such as a channel.
+    /// Frames are self-contained, so `send` may also pass them on to [`Follower::apply`] on the
+    /// other end as individual messages.
+    ///
+    /// Every publish produces a frame, even if it has no operations, so that followers can tell if
+    /// they missed one. The first frame is for the generation after the current one.
+    ///
+    /// If `send` fails, replication stops, and `send` is not called again. The returned
+    /// [`ReplicationStatus`] then reports the error. Publishing carries on as usual, so check the
+    /// status every now and then; the follower has to be set up anew once replication stopped.
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`on_publish`](Self::on_publish), that is, if nothing has been
+    /// published yet.
+    pub fn replicate_with<C, F>(&mut self, codec: C, mut send: F) -> ReplicationStatus
+    where
+        C: OpCodec<O> + Send + 'static,
+        F: FnMut(&[u8]) -> io::Result<()> + Send + 'static,
+    {
+        let status = ReplicationStatus {
+            inner: Arc::new(Mutex::new(Status::default())),
+        };
+        let reporter = status.clone();
+        let mut buf = Vec::new();
+        let mut broken = false;
+        self.on_publish(Box::new(move |batch| {
+            if broken {
+                return;
+            }
+            buf.clear();
+            frame::encode(
+                batch.generation() as u64,
+                batch.high_water_mark(),
+                batch,
+                |op, buf| codec.encode(op, buf),
+                &mut buf,
+            );
+            if let Err(e) = send(&buf) {
+                broken = true;
+                let mut status = reporter.status();
+                status.stopped = true;
+                status.error = Some(e);
+            }
+        }));
+        status
+    }
+}
+
+/// The error returned when a [`Follower`] cannot apply a frame.
+///
+/// The follower is left as it was in all cases, so it is safe to carry on after a
+/// [`Duplicate`](Self::Duplicate), for example after reconnecting to the leader.
+#[derive(Debug)]
+pub enum ReplicationError {
+    /// Reading or decoding the frame failed.
+    Io(io::Error),
+    /// The frame is for a later generation than the next one, so some were missed.
+    Gap {
+        /// The generation the follower expected next.
+        expected: usize,
+        /// The generation of the frame.
+        received: usize,
+    },
+    /// The frame is for a generation the follower has already applied.
+    Duplicate {
+        /// The generation the follower expected next.
+        expected: usize,
+        /// The generation of the frame.
+        received: usize,
+    },
+}
+
+impl fmt::Display for ReplicationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ReplicationError::Io(ref e) => write!(f, "failed to receive frame: {}", e),
+            ReplicationError::Gap { expected, received } => write!(
+                f,
+                "expected generation {}, but received {}; some were missed",
+                expected, received
+            ),
+            ReplicationError::Duplicate { expected, received } => write!(
+                f,
+                "expected generation {}, but received {}, which was already applied",
+                expected, received
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplicationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            ReplicationError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReplicationError {
+    fn from(e: io::Error) -> Self {
+        ReplicationError::Io(e)
+    }
+}
+
+/// Applies the batches a leader [replicates](WriteHandle::replicate_to) to a [`WriteHandle`] of
+/// its own.
+///
+/// Every frame is applied and published as is, so the follower's readers see exactly the versions
+/// the leader's readers saw, under the same generations. Frames must be applied in order:
+/// frames for any other generation than the next one are rejected with a
+/// [`ReplicationError`].
+pub struct Follower<T, O>
+where
+    T: Absorb<O>,
+{
+    w: WriteHandle<T, O>,
+    codec: Box<dyn OpCodec<O> + Send>,
+}
+
+impl<T, O> fmt::Debug for Follower<T, O>
+where
+    T: Absorb<O>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Follower")
+            .field("next_generation", &self.next_generation())
+            .finish()
+    }
+}
+
+impl<T, O> Follower<T, O>
+where
+    T: Absorb<O>,
+{
+    /// Follow a leader that started replicating when neither it nor `w` had published anything.
+    ///
+    /// This publishes `w` once if it has not published yet, just like the leader did when it
+    /// started replicating.
+    pub fn new<C>(mut w: WriteHandle<T, O>, codec: C) -> Self
+    where
+        C: OpCodec<O> + Send + 'static,
+    {
+        if w.generation() == 0 {
+            w.publish();
+        }
+        Self {
+            w,
+            codec: Box::new(codec),
+        }
+    }
+
+    /// Follow a leader whose readers saw the same data as the readers of `w` do now at
+    /// `generation`, such as when `w` was recovered from a [checkpoint](crate::persist) the leader
+    /// took.
+    ///
+    /// The next frame the follower accepts is the one for the generation after `generation`.
+    pub fn resume_at<C>(w: WriteHandle<T, O>, codec: C, generation: usize) -> Self
+    where
+        C: OpCodec<O> + Send + 'static,
+    {
+        let mut follower = Self::new(w, codec);
+        follower.w.resume_generation(generation);
+        follower
+    }
+
+    /// The generation of the frame the follower accepts next.
+    pub fn next_generation(&self) -> usize {
+        self.w.generation() + 1
+    }
+
+    /// Apply a single encoded frame, and return its generation.
+    pub fn apply(&mut self, frame: &[u8]) -> Result<usize, ReplicationError> {
+        let mut reader = frame;
+        match frame::read(&mut reader)? {
+            Some(frame) if reader.is_empty() => self.apply_frame(frame),
+            Some(_) => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "trailing bytes after frame").into())
+            }
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    /// Read the next frame from `reader` and apply it.
+    ///
+    /// Returns the generation of the frame, or `None` if `reader` has no more frames.
+    pub fn receive<R: Read>(&mut self, reader: &mut R) -> Result<Option<usize>, ReplicationError> {
+        match frame::read(reader)? {
+            Some(frame) => self.apply_frame(frame).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Apply frames from `reader` until it ends, or until one of them cannot be applied.
+    pub fn follow<R: Read>(&mut self, mut reader: R) -> Result<(), ReplicationError> {
+        while self.receive(&mut reader)?.is_some() {}
+        Ok(())
+    }
+
+    fn apply_frame(&mut self, frame: Frame) -> Result<usize, ReplicationError> {
+        let expected = self.next_generation();
+        let received = frame.generation as usize;
+        if received > expected {
+            return Err(ReplicationError::Gap { expected, received });
+        } else if received < expected {
+            return Err(ReplicationError::Duplicate { expected, received });
+        }
+
+        let codec = &self.codec;
+        let ops = frame.ops(|bytes| codec.decode(bytes))?;
+        self.w.extend(ops);
+        self.w.resume_high_water_mark(frame.high_water_mark);
+        self.w.publish();
+        debug_assert_eq!(self.w.generation(), received);
+        Ok(received)
+    }
+
+    /// Returns the follower's [`WriteHandle`], which readers can be created from.
+    pub fn write_handle(&self) -> &WriteHandle<T, O> {
+        &self.w
+    }
+
+    /// Stop following, and return the [`WriteHandle`] to take over as the leader, for example.
+    pub fn into_write_handle(self) -> WriteHandle<T, O> {
+        self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::I32Codec;
+    use crate::TryCompressResult;
+    use std::sync::{Arc, Mutex};
+    include!("./utilities.rs");
+
+    #[test]
+    #[cfg(unix)]
+    fn follow_over_socket() {
+        let (to_follower, from_leader) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (mut leader, leader_r) = crate::new::<i32, CounterAddOp>();
+        leader.append(CounterAddOp(1)).publish();
+        leader.replicate_to(I32Codec, to_follower);
+
+        // followers start out with the same data as the leader
+        let (mut follower, follower_r) = crate::new::<i32, CounterAddOp>();
+        follower.append(CounterAddOp(1));
+        let mut follower = Follower::new(follower, I32Codec);
+        let jh = std::thread::spawn(move || {
+            follower.follow(from_leader).unwrap();
+            follower
+        });
+
+        for i in 2..10 {
+            leader.append(CounterAddOp(i));
+            if i % 3 == 0 {
+                leader.publish();
+            }
+        }
+        leader.publish();
+        leader.publish();
+        let (value, generation) = (*leader_r.enter().unwrap(), leader_r.generation());
+        // closes the socket
+        drop(leader);
+
+        let follower = jh.join().unwrap();
+        assert_eq!(*follower_r.enter().unwrap(), value);
+        assert!(follower.next_generation() > generation);
+        assert_eq!(*follower.write_handle().enter().unwrap(), 45);
+    }
+
+    #[test]
+    fn send_failure_is_reported() {
+        let sent = Arc::new(Mutex::new(0));
+        let (mut leader, _r) = crate::new::<i32, CounterAddOp>();
+        leader.publish();
+        let attempts = Arc::clone(&sent);
+        let status = leader.replicate_with(I32Codec, move |_| {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts == 2 {
+                Err(io::ErrorKind::BrokenPipe.into())
+            } else {
+                Ok(())
+            }
+        });
+
+        leader.append(CounterAddOp(1)).publish();
+        assert!(!status.is_stopped());
+        assert!(status.take_error().is_none());
+
+        leader.append(CounterAddOp(2)).publish();
+        leader.append(CounterAddOp(3)).publish();
+        assert!(status.is_stopped());
+        assert_eq!(
+            status.take_error().unwrap().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert!(status.take_error().is_none());
+        assert!(status.is_stopped());
+        // send is not called again once it failed, but publishing carries on
+        assert_eq!(*sent.lock().unwrap(), 2);
+        assert_eq!(*leader.enter().unwrap(), 6);
+    }
+
+    #[test]
+    fn gaps_and_duplicates() {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let (mut leader, _r) = crate::new::<i32, CounterAddOp>();
+        leader.publish();
+        let sent = Arc::clone(&frames);
+        leader.replicate_with(I32Codec, move |frame| {
+            sent.lock().unwrap().push(frame.to_vec());
+            Ok(())
+        });
+        for i in 1..=3 {
+            leader.append_seq(i as u64, CounterAddOp(i));
+            leader.publish();
+        }
+        let frames = frames.lock().unwrap().clone();
+        assert_eq!(frames.len(), 3);
+
+        let (follower, r) = crate::new::<i32, CounterAddOp>();
+        let mut follower = Follower::new(follower, I32Codec);
+        assert_eq!(follower.next_generation(), 2);
+        match follower.apply(&frames[1]) {
+            Err(ReplicationError::Gap {
+                expected: 2,
+                received: 3,
+            }) => {}
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(follower.apply(&frames[0]).unwrap(), 2);
+        match follower.apply(&frames[0]) {
+            Err(ReplicationError::Duplicate {
+                expected: 3,
+                received: 2,
+            }) => {}
+            r => panic!("{:?}", r),
+        }
+        assert_eq!(*r.enter().unwrap(), 1);
+
+        // corrupt frames are rejected too
+        let mut corrupt = frames[1].clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            follower.apply(&corrupt),
+            Err(ReplicationError::Io(_))
+        ));
+
+        let mut stream = Vec::new();
+        for frame in &frames[1..] {
+            stream.extend_from_slice(frame);
+        }
+        follower.follow(&stream[..]).unwrap();
+        assert_eq!(*r.enter().unwrap(), 6);
+        assert_eq!(r.generation(), 4);
+        // the high-water mark comes along, so followers drop the same duplicates
+        assert_eq!(r.high_water_mark(), Some(3));
+        let mut w = follower.into_write_handle();
+        assert!(!w.append_seq(3, CounterAddOp(3)));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::sync::{AtomicBool, AtomicPtr, AtomicUsize, Mutex, MutexGuard, Ordering};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// State shared between the [`WriteHandle`](crate::WriteHandle) and all readers of a single
+/// left-right instance.
+pub(crate) struct Shared<T> {
+    /// The copy of the data that readers currently go through, or NULL once the writer is gone.
+    pub(crate) ptr: AtomicPtr<T>,
+    /// The number of times the writer has published, which identifies the copy behind `ptr`.
+    pub(crate) generation: AtomicUsize,
+    /// The high-water mark of the sequence numbers of the operations in the copy behind `ptr`.
+    pub(crate) high_water_mark: Mutex<Option<u64>>,
+    /// Set if an [`Absorb`](crate::Absorb) method panicked and the two copies may have diverged.
+    pub(crate) poisoned: AtomicBool,
+    /// Set once the writer has gone away, but left the copy behind `ptr` for the readers.
+    pub(crate) frozen: AtomicBool,
+    /// Drops the copy left behind for the readers once the last of them is gone.
+    ///
+    /// Readers know nothing about the operation type, so the writer leaves behind the right
+    /// [`Absorb::drop_second`](crate::Absorb::drop_second) here.
+    pub(crate) drop_frozen: Mutex<Option<unsafe fn(*mut T)>>,
+    /// Owned copies of published data handed out by [`ReadHandle::snapshot`].
+    ///
+    /// [`ReadHandle::snapshot`]: crate::ReadHandle::snapshot
+    pub(crate) snapshots: Mutex<Snapshots<T>>,
+    /// The history of the most recent [`ChangeFeed`](crate::ChangeFeed), for
+    /// [`ReadHandle::changes_since`](crate::ReadHandle::changes_since).
+    ///
+    /// Readers know nothing about the operation type, so it is type-erased.
+    pub(crate) change_feed: Mutex<Option<Arc<dyn Any + Send + Sync>>>,
+}
+
+// Like the `AtomicPtr<T>` it wraps, `Shared` can be sent and shared whatever `T` is. It does end up
+// owning `T`s, namely the copy behind `ptr` once the writer is frozen, and the ones in `snapshots`.
+// But the methods that make it so require `T: Send` and `T: Send + Sync` respectively.
+unsafe impl<T> Send for Shared<T> {}
+unsafe impl<T> Sync for Shared<T> {}
+
+pub(crate) struct Snapshots<T> {
+    /// Set by readers that want the writer to clone the next copy it publishes.
+    ///
+    /// Readers know that `T: Clone`, the writer does not, so they leave `T::clone` here.
+    pub(crate) requested: Option<fn(&T) -> T>,
+    /// The most recent snapshot, along with the address of the copy it was taken from.
+    pub(crate) latest: Option<(usize, Arc<T>)>,
+    /// The versions kept around by [`WriteHandle::retain_versions`], if enabled.
+    ///
+    /// [`WriteHandle::retain_versions`]: crate::WriteHandle::retain_versions
+    pub(crate) retained: Option<Retained<T>>,
+}
+
+pub(crate) struct Retained<T> {
+    pub(crate) keep: usize,
+    /// The writer only knows that `T: Clone` when retention is enabled, so it leaves `T::clone`
+    /// here for when it publishes.
+    pub(crate) clone: fn(&T) -> T,
+    /// Clones of the most recently published versions, along with their generation, oldest first.
+    pub(crate) versions: VecDeque<(usize, Arc<T>)>,
+}
+
+impl<T> Retained<T> {
+    pub(crate) fn push(&mut self, generation: usize, t: Arc<T>) {
+        while self.versions.len() >= self.keep {
+            self.versions.pop_front();
+        }
+        self.versions.push_back((generation, t));
+    }
+}
+
+impl<T> Shared<T> {
+    pub(crate) fn new(store: *mut T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(store),
+            generation: AtomicUsize::new(0),
+            high_water_mark: Mutex::new(None),
+            poisoned: AtomicBool::new(false),
+            frozen: AtomicBool::new(false),
+            drop_frozen: Mutex::new(None),
+            snapshots: Mutex::new(Snapshots {
+                requested: None,
+                latest: None,
+                retained: None,
+            }),
+            change_feed: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn high_water_mark(&self) -> MutexGuard<'_, Option<u64>> {
+        // there is nothing to leave half-way updated.
+        self.high_water_mark
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub(crate) fn snapshots(&self) -> MutexGuard<'_, Snapshots<T>> {
+        // a `T::clone` that panics while the lock is held leaves nothing half-way updated.
+        self.snapshots
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Called by the writer right before it makes `copy` visible to readers.
+    ///
+    /// The latest snapshot may have been taken from `copy` back when it was last published, so
+    /// it is always replaced, either with a clone of `copy` if a reader asked for one or versions
+    /// are retained, or with nothing.
+    pub(crate) fn publishing(&self, copy: &T) {
+        let clone = {
+            let mut snapshots = self.snapshots();
+            snapshots.latest = None;
+            match snapshots.retained {
+                Some(ref retained) => Some(retained.clone),
+                None => snapshots.requested.take(),
+            }
+        };
+        let clone = match clone {
+            // clone without holding the lock, so that readers taking snapshots of the copy they
+            // can currently see are not held up by it.
+            Some(clone) => Arc::new(clone(copy)),
+            None => return,
+        };
+
+        let mut snapshots = self.snapshots();
+        let snapshots = &mut *snapshots;
+        // requests made while we were cloning are served by this clone just as well.
+        snapshots.requested = None;
+        if let Some(ref mut retained) = snapshots.retained {
+            // only the writer changes the generation, and it is about to bump it.
+            let generation = self.generation.load(Ordering::Relaxed) + 1;
+            retained.push(generation, Arc::clone(&clone));
+        }
+        snapshots.latest = Some((copy as *const T as usize, clone));
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // the writer holds a reader of its own, so if we get here the writer is gone, and either
+        // took the data down with it or left it behind for us.
+        if let Some(drop_frozen) = self.drop_frozen.lock().unwrap().take() {
+            // safety: the copy behind ptr was created from a `Box`, and since all readers are gone
+            // it is no longer aliased.
+            unsafe { drop_frozen(self.ptr.load(Ordering::Acquire)) };
+        }
+    }
+}
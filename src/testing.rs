@@ -0,0 +1,334 @@
+//! Property testing for [`Absorb`] implementations.
+//!
+//! Most bugs in `Absorb` implementations only show up for particular interleavings of appends
+//! and publishes: an `absorb_second` that does something other than `absorb_first`, a
+//! `try_compress` that merges operations that do not commute, or a `sync_with` that misses
+//! part of the data. [`check_absorb`] runs a randomly generated [`Script`] against a left-right
+//! instance, and checks it against a simple reference model of what the operations should do
+//! every step of the way.
+//!
+//! `Script` implements [`quickcheck::Arbitrary`] for any operation type that does, so it can be
+//! used directly as the input of a quickcheck property. Failing scripts are shrunk to the
+//! smallest one that still fails.
+//!
+//! This makes `quickcheck` a public dependency of the `testing` feature: operation types have to
+//! implement the `Arbitrary` of the same `quickcheck` version as this crate uses, which is 1.x.
+//!
+//! ```
+//! use left_right::testing::{check_absorb, Script};
+//! use left_right::{Absorb, TryCompressResult};
+//! use quickcheck::{Arbitrary, Gen};
+//!
+//! #[derive(Debug, Clone)]
+//! enum CounterOp {
+//!     Add(i32),
+//!     Set(i32),
+//! }
+//!
+//! impl Arbitrary for CounterOp {
+//!     fn arbitrary(g: &mut Gen) -> Self {
+//!         if bool::arbitrary(g) {
+//!             CounterOp::Add(i8::arbitrary(g) as i32)
+//!         } else {
+//!             CounterOp::Set(i8::arbitrary(g) as i32)
+//!         }
+//!     }
+//! }
+//!
+//! impl Absorb<CounterOp> for i32 {
+//!     fn absorb_first(&mut self, operation: &mut CounterOp, _: &Self) {
+//!         match *operation {
+//!             CounterOp::Add(v) => *self += v,
+//!             CounterOp::Set(v) => *self = v,
+//!         }
+//!     }
+//!
+//!     fn sync_with(&mut self, first: &Self) {
+//!         *self = *first
+//!     }
+//!
+//!     const MAX_COMPRESS_RANGE: usize = 4;
+//!
+//!     fn try_compress(prev: &mut CounterOp, next: CounterOp) -> TryCompressResult<CounterOp> {
+//!         match (prev, next) {
+//!             (CounterOp::Add(prev), CounterOp::Add(next)) => {
+//!                 *prev += next;
+//!                 TryCompressResult::Compressed
+//!             }
+//!             (prev, CounterOp::Set(next)) => {
+//!                 *prev = CounterOp::Set(next);
+//!                 TryCompressResult::Compressed
+//!             }
+//!             (_, next) => TryCompressResult::Dependent(next),
+//!         }
+//!     }
+//! }
+//!
+//! fn counter_matches_model(script: Script<CounterOp>) {
+//!     check_absorb(
+//!         |counter: &mut i32, op: &CounterOp| match *op {
+//!             CounterOp::Add(v) => *counter += v,
+//!             CounterOp::Set(v) => *counter = v,
+//!         },
+//!         script,
+//!     )
+//! }
+//!
+//! quickcheck::quickcheck(counter_matches_model as fn(Script<CounterOp>));
+//! ```
+
+use crate::Absorb;
+use quickcheck::{Arbitrary, Gen};
+use std::fmt;
+use std::ops::Range;
+
+/// A single step of a [`Script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step<O> {
+    /// Add the operations to the operational log.
+    ///
+    /// Only the operations in the `compress` range of `ops` are [compressed](Absorb::try_compress)
+    /// with the ones already in the log as they are added. The ones before and after it are added
+    /// as if [`MAX_COMPRESS_RANGE`](Absorb::MAX_COMPRESS_RANGE) was zero. Either way, they may
+    /// still be compressed with operations that are added after them.
+    Extend {
+        /// The operations to add.
+        ops: Vec<O>,
+        /// The indices into `ops` of the operations to compress as they are added.
+        ///
+        /// Indices past the end of `ops` are ignored.
+        compress: Range<usize>,
+    },
+    /// [Publish](crate::WriteHandle::publish), and check that readers see the model.
+    Publish,
+    /// Check that both copies match, with
+    /// [`verify_consistency`](crate::WriteHandle::verify_consistency).
+    Verify,
+}
+
+/// A sequence of appends and publishes for [`check_absorb`] to run.
+///
+/// Generated scripts mostly add short runs of operations, and publish or verify in between every
+/// so often. A script always ends with a [`take`](crate::WriteHandle::take).
+#[derive(Clone, PartialEq, Eq)]
+pub struct Script<O> {
+    steps: Vec<Step<O>>,
+}
+
+impl<O> Script<O> {
+    /// Create a script that runs the given steps.
+    pub fn new(steps: Vec<Step<O>>) -> Self {
+        Self { steps }
+    }
+
+    /// The steps of the script, in the order they are run.
+    pub fn steps(&self) -> &[Step<O>] {
+        &self.steps
+    }
+}
+
+impl<O: fmt::Debug> fmt::Debug for Script<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.steps).finish()
+    }
+}
+
+impl<O: Arbitrary> Arbitrary for Step<O> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match u8::arbitrary(g) % 8 {
+            0..=4 => {
+                // keep the runs short, so that there are enough publishes in between.
+                let len = usize::arbitrary(g) % 16 + 1;
+                // compression is the interesting case, but skipping it for some of the operations
+                // leaves operations around that compression would not have.
+                let compress = if bool::arbitrary(g) {
+                    0..len
+                } else {
+                    let start = usize::arbitrary(g) % (len + 1);
+                    start..start + usize::arbitrary(g) % (len - start + 1)
+                };
+                Step::Extend {
+                    ops: (0..len).map(|_| O::arbitrary(g)).collect(),
+                    compress,
+                }
+            }
+            5 | 6 => Step::Publish,
+            _ => Step::Verify,
+        }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        match self {
+            Step::Extend { ops, compress } => {
+                let uncompressed = if compress.start < compress.end {
+                    Some(Step::Extend {
+                        ops: ops.clone(),
+                        compress: 0..0,
+                    })
+                } else {
+                    None
+                };
+                let compress = compress.clone();
+                Box::new(
+                    uncompressed
+                        .into_iter()
+                        .chain(ops.shrink().filter(|ops| !ops.is_empty()).map(move |ops| {
+                            Step::Extend {
+                                compress: compress.start.min(ops.len())
+                                    ..compress.end.min(ops.len()),
+                                ops,
+                            }
+                        })),
+                )
+            }
+            Step::Publish | Step::Verify => quickcheck::empty_shrinker(),
+        }
+    }
+}
+
+impl<O: Arbitrary> Arbitrary for Script<O> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self::new(Vec::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        Box::new(self.steps.shrink().map(Self::new))
+    }
+}
+
+/// Run `script` against a new left-right instance, and check that it agrees with `model`
+/// throughout.
+///
+/// `model` applies a single operation to a plain `T`, the way the operation is meant to behave,
+/// without any of the trickery `Absorb` implementations get up to. The model starts out as
+/// `T::default()`, just like the left-right instance, and every operation the script adds is
+/// applied to it right away. Then:
+///
+///  - after every publish, readers must see the model;
+///  - whenever the script says so, and right before the end, the two copies must be the same;
+///  - and at the end, [`take`](crate::WriteHandle::take) must return the model.
+///
+/// The first publish cycle applies operations directly to the data, so a script needs a publish
+/// early on to get to the operational log, and to compression.
+///
+/// # Panics
+///
+/// Panics if the left-right instance disagrees with the model, or with itself, and describes
+/// the first step where it did. So this is meant to be called from a quickcheck property, or
+/// a test.
+pub fn check_absorb<T, O, F>(mut model: F, script: Script<O>)
+where
+    T: Absorb<O> + Default + PartialEq + fmt::Debug,
+    O: fmt::Debug,
+    F: FnMut(&mut T, &O),
+{
+    let (mut w, r) = crate::new::<T, O>();
+    let mut expected = T::default();
+
+    for (i, step) in script.steps.into_iter().enumerate() {
+        match step {
+            Step::Extend { mut ops, compress } => {
+                for op in &ops {
+                    model(&mut expected, op);
+                }
+                let end = compress.end.min(ops.len());
+                let start = compress.start.min(end);
+                let after = ops.split_off(end);
+                let compressed = ops.split_off(start);
+                w.extend_uncompressed(ops);
+                w.extend(compressed);
+                w.extend_uncompressed(after);
+            }
+            Step::Publish => {
+                w.publish();
+                let data = r.enter().expect("the instance is not poisoned");
+                assert_eq!(
+                    *data, expected,
+                    "readers disagree with the model at step {}",
+                    i
+                );
+            }
+            Step::Verify => {
+                if let Err(divergence) = w.verify_consistency(|a, b| a == b) {
+                    panic!("{} at step {}", divergence, i);
+                }
+            }
+        }
+    }
+
+    w.publish();
+    if let Err(divergence) = w.verify_consistency(|a, b| a == b) {
+        panic!("{} at the end", divergence);
+    }
+    let data = w.take();
+    assert_eq!(*data, expected, "the taken data disagrees with the model");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_absorb, Script, Step};
+    use crate::{Absorb, TryCompressResult};
+    use quickcheck::{Arbitrary, Gen};
+    use quickcheck_macros::quickcheck;
+    include!("./utilities.rs");
+
+    type Op = CompressibleCounterOp<3>;
+
+    impl Clone for Op {
+        fn clone(&self) -> Self {
+            match *self {
+                Op::Set(v) => Op::Set(v),
+                Op::Add(v) => Op::Add(v),
+                Op::Sub(v) => Op::Sub(v),
+            }
+        }
+    }
+
+    impl Arbitrary for Op {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let v = i8::arbitrary(g) as i32;
+            match u8::arbitrary(g) % 5 {
+                0 => Op::Set(v),
+                1 | 2 => Op::Add(v),
+                _ => Op::Sub(v),
+            }
+        }
+    }
+
+    fn model(counter: &mut i32, op: &Op) {
+        match *op {
+            Op::Set(v) => *counter = v,
+            Op::Add(v) => *counter += v,
+            Op::Sub(v) => *counter -= v,
+        }
+    }
+
+    #[quickcheck]
+    fn compressible_counter(script: Script<Op>) {
+        check_absorb(model, script)
+    }
+
+    #[test]
+    #[should_panic(expected = "readers disagree with the model at step 3")]
+    fn wrong_model() {
+        check_absorb(
+            |counter: &mut i32, op: &Op| match *op {
+                // subtracting is easy to get backwards
+                Op::Sub(v) => *counter += v,
+                ref op => model(counter, op),
+            },
+            Script::new(vec![
+                Step::Publish,
+                Step::Extend {
+                    ops: vec![Op::Add(1), Op::Add(2), Op::Add(3)],
+                    compress: 1..2,
+                },
+                Step::Extend {
+                    ops: vec![Op::Sub(1)],
+                    compress: 0..0,
+                },
+                Step::Publish,
+            ]),
+        )
+    }
+}
@@ -13,6 +13,20 @@ impl Absorb<CounterAddOp> for i32 {
     }
 }
 
+#[cfg(test)]
+impl From<i32> for CounterAddOp {
+    fn from(n: i32) -> Self {
+        CounterAddOp(n)
+    }
+}
+
+#[cfg(test)]
+impl From<&CounterAddOp> for i32 {
+    fn from(operation: &CounterAddOp) -> Self {
+        operation.0
+    }
+}
+
 #[cfg(test)]
 #[derive(Debug, Eq, PartialEq)]
 pub enum CompressibleCounterOp<const MAX_COMPRESS_RANGE: usize> {
@@ -68,3 +82,24 @@ impl<const MAX_COMPRESS_RANGE: usize> Absorb<CompressibleCounterOp<MAX_COMPRESS_
         }
     }
 }
+
+#[cfg(test)]
+#[derive(Debug)]
+pub enum PanickingCounterOp {
+    Add(i32),
+    Panic,
+}
+
+#[cfg(test)]
+impl Absorb<PanickingCounterOp> for i32 {
+    fn absorb_first(&mut self, operation: &mut PanickingCounterOp, _: &Self) {
+        match operation {
+            PanickingCounterOp::Add(v) => *self += *v,
+            PanickingCounterOp::Panic => panic!("absorb panicked"),
+        }
+    }
+
+    fn sync_with(&mut self, first: &Self) {
+        *self = *first
+    }
+}
@@ -1,12 +1,16 @@
-use crate::read::ReadHandle;
-use crate::Absorb;
+use crate::read::{ReadHandle, ReaderInfo};
+use crate::shared::Retained;
+use crate::{Absorb, Invertible};
 
-use crate::sync::{fence, Arc, AtomicUsize, MutexGuard, Ordering};
+#[cfg(test)]
+use crate::sync::Arc;
+use crate::sync::{fence, Ordering};
 use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::ops::{DerefMut, Range};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr::NonNull;
-#[cfg(test)]
+#[cfg(any(test, feature = "persist"))]
 use std::sync::atomic::AtomicBool;
 use std::{fmt, thread};
 
@@ -38,12 +42,55 @@ where
     is_waiting: Arc<AtomicBool>,
     /// Write directly to the write handle map, since no publish has happened.
     first: bool,
+    /// Operations have been written directly to the write handle map.
+    wrote_directly: bool,
     /// A publish has happened, but the two copies have not been synchronized yet.
     second: bool,
     /// If we call `Self::take` the drop needs to be different.
     taken: bool,
+    /// Freeze instead of tearing down the data if dropped while the thread is panicking.
+    freeze_on_panic: bool,
+    /// Called with the operations that became visible after each publish.
+    publish_hooks: Vec<(PublishHookId, PublishHook<O>)>,
+    next_hook_id: u64,
+    /// The highest sequence number passed to `append_seq`, and the one readers can see.
+    appended_seq: Option<u64>,
+    published_seq: Option<u64>,
+    /// Compares the two copies every time the stale one has caught up, if set.
+    verifier: Option<Verifier<T, O>>,
+    /// The segments of the log this instance is persisted to, if any.
+    #[cfg(feature = "persist")]
+    pub(crate) log: Option<crate::persist::SharedSegments>,
+    /// Set if writing to the log failed, after which publishing again would leave a gap in it.
+    #[cfg(feature = "persist")]
+    log_failed: Option<std::sync::Arc<AtomicBool>>,
+}
+
+enum PublishHook<O> {
+    /// Registered through [`WriteHandle::on_publish`], and called until it is removed.
+    Always(Box<dyn FnMut(Batch<'_, O>) + Send>),
+    /// Returns false once nobody is interested in batches anymore, after which it is dropped.
+    WhileWanted(Box<dyn FnMut(Batch<'_, O>) -> bool + Send>),
+}
+
+impl<O> PublishHook<O> {
+    /// Returns false if the hook should be dropped.
+    fn call(&mut self, batch: Batch<'_, O>) -> bool {
+        match self {
+            PublishHook::Always(hook) => {
+                hook(batch);
+                true
+            }
+            PublishHook::WhileWanted(hook) => hook(batch),
+        }
+    }
 }
 
+/// Identifies a hook registered with [`WriteHandle::on_publish`], so that it can be
+/// [removed](WriteHandle::remove_publish_hook) again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublishHookId(u64);
+
 // safety: if a `WriteHandle` is sent across a thread boundary, we need to be able to take
 // ownership of both Ts and Os across that thread boundary. since `WriteHandle` holds a
 // `ReadHandle`, we also need to respect its Send requirements.
@@ -70,6 +117,8 @@ where
             .field("r_handle", &self.r_handle)
             .field("first", &self.first)
             .field("second", &self.second)
+            .field("publish_hooks", &self.publish_hooks.len())
+            .field("verified", &self.verifier.is_some())
             .finish()
     }
 }
@@ -115,8 +164,12 @@ impl<T: Absorb<O>, O> DerefMut for Taken<T, O> {
 }
 
 impl<T: Absorb<O>, O> Taken<T, O> {
-    /// This is unsafe because you must call [`Absorb::drop_second`] in
-    /// case just dropping `T` is not safe and sufficient.
+    /// Unwraps the taken data into a plain `Box<T>`.
+    ///
+    /// # Safety
+    ///
+    /// You must call [`Absorb::drop_second`] in case just dropping `T` is not safe and
+    /// sufficient.
     ///
     /// If you used the default implementation of [`Absorb::drop_second`] (which just calls [`drop`](Drop::drop))
     /// you don't need to call [`Absorb::drop_second`].
@@ -135,6 +188,121 @@ impl<T: Absorb<O>, O> Drop for Taken<T, O> {
     }
 }
 
+/// The operations that became visible to readers in a single call to [`WriteHandle::publish`].
+///
+/// Handed to the hooks registered with [`WriteHandle::on_publish`].
+pub struct Batch<'a, O> {
+    ops: &'a VecDeque<Option<O>>,
+    generation: usize,
+    high_water_mark: Option<u64>,
+}
+
+impl<O> Clone for Batch<'_, O> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<O> Copy for Batch<'_, O> {}
+
+impl<O: fmt::Debug> fmt::Debug for Batch<'_, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("generation", &self.generation)
+            .field("high_water_mark", &self.high_water_mark)
+            .field("ops", &self.iter().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<'a, O> Batch<'a, O> {
+    /// The [generation](ReadHandle::generation) that these operations became visible in.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// The [high-water mark](ReadHandle::high_water_mark) as of this publish.
+    ///
+    /// This may have moved even if the batch is empty, since the operations that moved it may
+    /// have been [compressed](Absorb::try_compress) away.
+    pub fn high_water_mark(&self) -> Option<u64> {
+        self.high_water_mark
+    }
+
+    /// Iterate over the operations, in the order they were applied.
+    ///
+    /// Operations that were [compressed](Absorb::try_compress) away are not included.
+    pub fn iter(&self) -> impl Iterator<Item = &'a O> + 'a {
+        self.ops.iter().flatten()
+    }
+
+    /// Returns the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns true if no operations became visible in this publish.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl<'a, O> IntoIterator for Batch<'a, O> {
+    type Item = &'a O;
+    type IntoIter = std::iter::Flatten<std::collections::vec_deque::Iter<'a, Option<O>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.iter().flatten()
+    }
+}
+
+/// The two copies of a left-right instance turned out to differ after both absorbed the same
+/// operations.
+///
+/// Returned by [`WriteHandle::verify_consistency`], and the panic message of
+/// [`WriteHandle::verify_every_publish`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    generation: usize,
+    ops: Vec<String>,
+}
+
+impl Divergence {
+    /// The [generation](ReadHandle::generation) the stale copy was brought up to before the
+    /// copies were compared.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// The `Debug` representations of the operations the stale copy absorbed to catch up, in the
+    /// order they were applied.
+    ///
+    /// If the copies already differed before these, the divergence happened in an earlier publish
+    /// that was not checked.
+    pub fn ops(&self) -> &[String] {
+        &self.ops
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the two copies differ after catching up to generation {} with operations [{}]",
+            self.generation,
+            self.ops.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// What [`WriteHandle::verify_every_publish`] compares the copies with.
+struct Verifier<T, O> {
+    eq: Box<dyn Fn(&T, &T) -> bool + Send>,
+    describe: fn(&O) -> String,
+}
+
 impl<T, O> WriteHandle<T, O>
 where
     T: Absorb<O>,
@@ -155,26 +323,25 @@ where
         self.taken = true;
 
         // first, ensure both copies are up to date
-        // (otherwise safely dropping the possibly duplicated w_handle data is a pain)
-        if self.first || !self.oplog.is_empty() {
-            self.publish();
-        }
-        if !self.oplog.is_empty() {
-            self.publish();
-        }
-        assert!(self.oplog.is_empty());
+        self.publish_all();
 
         // next, grab the read handle and set it to NULL
-        let r_handle = self.r_handle.inner.swap(ptr::null_mut(), Ordering::Release);
-
-        // now, wait for all readers to depart
-        let epochs = Arc::clone(&self.epochs);
-        let mut epochs = epochs.lock().unwrap();
-        self.wait(&mut epochs);
+        let r_handle = self
+            .r_handle
+            .inner
+            .ptr
+            .swap(ptr::null_mut(), Ordering::Release);
 
         // ensure that the subsequent epoch reads aren't re-ordered to before the swap
         fence(Ordering::SeqCst);
 
+        // readers may have entered the r_handle since the last publish recorded their epochs,
+        // so record them again now that no new reader can get at the r_handle.
+        self.record_epochs();
+
+        // now, wait for all readers to depart
+        self.wait(&self.epochs);
+
         // all readers have now observed the NULL, so we own both handles.
         // all operations have been applied to both w_handle and r_handle.
         // give the underlying data structure an opportunity to handle the one copy differently:
@@ -197,12 +364,55 @@ where
     }
 }
 
+impl<T, O> WriteHandle<T, O>
+where
+    T: Absorb<O>,
+{
+    /// Ensure that both copies of the data have seen every operation in the oplog, so that one of
+    /// them can be dropped without the other missing anything.
+    ///
+    /// If the instance is poisoned, publishing again would just replay the oplog against
+    /// inconsistent state. The read copy is still the one that was last published successfully
+    /// in that case, so the operations that did not make it are discarded instead. The same goes
+    /// if writing to the log failed, since publishing would leave a gap in it.
+    fn publish_all(&mut self) {
+        if self.is_poisoned() || self.has_log_failed() {
+            self.oplog.clear();
+        } else {
+            if self.first || !self.oplog.is_empty() {
+                self.publish();
+            }
+            if !self.oplog.is_empty() {
+                self.publish();
+            }
+        }
+        assert!(self.oplog.is_empty());
+    }
+}
+
+/// Drops a copy left behind by [`WriteHandle::freeze`].
+///
+/// # Safety
+///
+/// `ptr` must have been created from a `Box`, and must no longer be aliased.
+unsafe fn drop_frozen<T, O>(ptr: *mut T)
+where
+    T: Absorb<O>,
+{
+    Absorb::drop_second(Box::from_raw(ptr));
+}
+
 impl<T, O> Drop for WriteHandle<T, O>
 where
     T: Absorb<O>,
 {
     fn drop(&mut self) {
-        if let Some(inner) = self.take_inner() {
+        if thread::panicking() && self.freeze_on_panic && !self.taken {
+            // the writer is going down with its thread. publishing from here could easily panic
+            // again (and abort), and the readers would lose the data for good, so instead leave
+            // the last published copy behind for them, and for a writer that may take over.
+            self.leave_behind();
+        } else if let Some(inner) = self.take_inner() {
             drop(inner);
         }
     }
@@ -213,6 +423,8 @@ where
     T: Absorb<O>,
 {
     pub(crate) fn new(w_handle: T, epochs: crate::Epochs, r_handle: ReadHandle<T>) -> Self {
+        // a writer that takes over carries on from the mark its predecessor published.
+        let seq = *r_handle.inner.high_water_mark();
         Self {
             epochs,
             // safety: Box<T> is not null and covariant.
@@ -226,12 +438,36 @@ where
             #[cfg(test)]
             refreshes: 0,
             first: true,
+            wrote_directly: false,
             second: true,
             taken: false,
+            freeze_on_panic: false,
+            publish_hooks: Vec::new(),
+            next_hook_id: 0,
+            appended_seq: seq,
+            published_seq: seq,
+            verifier: None,
+            #[cfg(feature = "persist")]
+            log: None,
+            #[cfg(feature = "persist")]
+            log_failed: None,
         }
     }
 
-    fn wait(&mut self, epochs: &mut MutexGuard<'_, slab::Slab<Arc<AtomicUsize>>>) {
+    /// Record the epochs of all readers right after a swap, for [`wait`](Self::wait) to compare
+    /// against.
+    ///
+    /// Must be called after a `SeqCst` fence that follows the swap.
+    fn record_epochs(&mut self) {
+        self.last_epochs.clear();
+        self.last_epochs.extend(
+            self.epochs
+                .iter()
+                .map(|epoch| epoch.load(Ordering::Acquire)),
+        );
+    }
+
+    fn wait(&self, epochs: &crate::epochs::EpochRegistry) {
         let mut iter = 0;
         let mut starti = 0;
 
@@ -239,11 +475,18 @@ where
         {
             self.is_waiting.store(true, Ordering::Relaxed);
         }
-        // we're over-estimating here, but slab doesn't expose its max index
-        self.last_epochs.resize(epochs.capacity(), 0);
         'retry: loop {
             // read all and see if all have changed (which is likely)
-            for (ii, (ri, epoch)) in epochs.iter().enumerate().skip(starti) {
+            //
+            // readers registered since we recorded the epochs have slots past the end of
+            // `last_epochs`, and must have seen the swap, so we need not look at them. readers may
+            // also come and go while we scan, which is fine for the same reason.
+            for (ri, epoch) in epochs
+                .iter()
+                .take(self.last_epochs.len())
+                .enumerate()
+                .skip(starti)
+            {
                 // if the reader's epoch was even last we read it (which was _after_ the swap),
                 // then they either do not have the pointer, or must have read the pointer strictly
                 // after the swap. in either case, they cannot be using the old pointer value (what
@@ -253,9 +496,9 @@ where
                 // which is odd, and std::u{N}::MAX + 1 == 0 is even.
                 //
                 // note also that `ri` _may_ have been re-used since we last read into last_epochs.
-                // this is okay though, as a change still implies that the new reader must have
-                // arrived _after_ we did the atomic swap, and thus must also have seen the new
-                // pointer.
+                // this is okay though, as the epoch in a slot keeps counting up across readers, so
+                // a change still implies that the new reader must have arrived _after_ we did the
+                // atomic swap, and thus must also have seen the new pointer.
                 if self.last_epochs[ri] % 2 == 0 {
                     continue;
                 }
@@ -268,7 +511,7 @@ where
                 } else {
                     // reader may not have seen swap
                     // continue from this reader's epoch
-                    starti = ii;
+                    starti = ri;
 
                     if !cfg!(loom) {
                         // how eagerly should we retry?
@@ -299,67 +542,46 @@ where
     /// it can replay the operational log onto the stale copy the readers used to use. This can
     /// take some time, especially if readers are executing slow operations, or if there are many
     /// of them.
+    ///
+    /// # Panics
+    ///
+    /// If one of the [`Absorb`] methods panics while the operations are applied, the left-right
+    /// instance is poisoned and the panic is propagated. Readers will no longer be able to
+    /// [`enter`](ReadHandle::enter) until [`recover`](Self::recover) is called.
+    ///
+    /// Panics if the instance is already poisoned. With the `persist` feature, this also panics if
+    /// writing to the log of the instance failed during an earlier publish.
     pub fn publish(&mut self) -> &mut Self {
+        assert!(
+            !self.is_poisoned(),
+            "cannot publish to a poisoned left-right instance; call `recover` first"
+        );
+        self.assert_log_intact();
+
         // we need to wait until all epochs have changed since the swaps *or* until a "finished"
         // flag has been observed to be on for two subsequent iterations (there still may be some
         // readers present since we did the previous refresh)
         //
-        // NOTE: it is safe for us to hold the lock for the entire duration of the swap. we will
-        // only block on pre-existing readers, and they are never waiting to push onto epochs
-        // unless they have finished reading.
-        let epochs = Arc::clone(&self.epochs);
-        let mut epochs = epochs.lock().unwrap();
-
-        self.wait(&mut epochs);
-
-        if !self.first {
-            // all the readers have left!
-            // safety: we haven't freed the Box, and no readers are accessing the w_handle
-            let w_handle = unsafe { self.w_handle.as_mut() };
-
-            // safety: we will not swap while we hold this reference
-            let r_handle = unsafe {
-                self.r_handle
-                    .inner
-                    .load(Ordering::Acquire)
-                    .as_ref()
-                    .unwrap()
-            };
-
-            if self.second {
-                Absorb::sync_with(w_handle, r_handle);
-                self.second = false
-            }
-
-            // the w_handle copy has not seen any of the writes in the oplog
-            // the r_handle copy has not seen any of the writes following swap_index
-            if self.swap_index != 0 {
-                // we can drain out the operations that only the w_handle copy needs
-                //
-                // NOTE: the if above is because drain(0..0) would remove 0
-                for op in self
-                    .oplog
-                    .drain(0..self.swap_index)
-                    .map(|opt| opt.expect("Nones are always temporary"))
-                {
-                    T::absorb_second(w_handle, op, r_handle);
-                }
-            }
-            // we cannot give owned operations to absorb_first
-            // since they'll also be needed by the r_handle copy
-            for op in self
-                .oplog
-                .iter_mut()
-                .map(|op| op.as_mut().expect("Nones are always temporary"))
-            {
-                T::absorb_first(w_handle, op, r_handle);
+        // NOTE: readers register and deregister without waiting for us, so new readers are never
+        // held up by a slow publish.
+        self.wait(&self.epochs);
+
+        // an `Absorb` method may panic half-way through, which leaves the w_handle copy in an
+        // unknown state. catch the unwind and poison the left-right instance instead.
+        let absorbed = panic::catch_unwind(AssertUnwindSafe(|| {
+            if !self.first {
+                self.absorb_pending();
+            } else {
+                self.first = false
             }
-            // the w_handle copy is about to become the r_handle, and can ignore the oplog
-            self.swap_index = self.oplog.len();
 
-        // w_handle (the old r_handle) is now fully up to date!
-        } else {
-            self.first = false
+            // safety: no readers are accessing the w_handle, and it's fully up to date.
+            let w_handle = unsafe { self.w_handle.as_ref() };
+            self.r_handle.inner.publishing(w_handle);
+        }));
+        if let Err(e) = absorbed {
+            self.r_handle.inner.poisoned.store(true, Ordering::Release);
+            panic::resume_unwind(e);
         }
 
         // at this point, we have exclusive access to w_handle, and it is up-to-date with all
@@ -374,17 +596,44 @@ where
         let r_handle = self
             .r_handle
             .inner
+            .ptr
             .swap(self.w_handle.as_ptr(), Ordering::Release);
 
         // NOTE: at this point, there are likely still readers using r_handle.
         // safety: r_handle was also created from a Box, so it is not null and is covariant.
         self.w_handle = unsafe { NonNull::new_unchecked(r_handle) };
+        let generation = self
+            .r_handle
+            .inner
+            .generation
+            .fetch_add(1, Ordering::Release)
+            + 1;
+        if self.published_seq != self.appended_seq {
+            // readers may see the new data before the new mark, but never the other way around.
+            *self.r_handle.inner.high_water_mark() = self.appended_seq;
+            self.published_seq = self.appended_seq;
+        }
 
         // ensure that the subsequent epoch reads aren't re-ordered to before the swap
         fence(Ordering::SeqCst);
 
-        for (ri, epoch) in epochs.iter() {
-            self.last_epochs[ri] = epoch.load(Ordering::Acquire);
+        self.record_epochs();
+
+        // all the operations that were absorbed by the old w_handle, and so just became visible,
+        // are now at the front of the oplog, and stay there until the next publish absorbs them
+        // into the other copy.
+        let batch = Batch {
+            ops: &self.oplog,
+            generation,
+            high_water_mark: self.published_seq,
+        };
+        let mut i = 0;
+        while i < self.publish_hooks.len() {
+            if self.publish_hooks[i].1.call(batch) {
+                i += 1;
+            } else {
+                self.publish_hooks.remove(i);
+            }
         }
 
         #[cfg(test)]
@@ -395,6 +644,119 @@ where
         self
     }
 
+    /// Bring the w_handle copy up to date with all the operations in the oplog.
+    ///
+    /// Must only be called once all readers have departed from the w_handle copy.
+    fn absorb_pending(&mut self) {
+        let ops = self
+            .verifier
+            .as_ref()
+            .map(|verifier| self.catching_up().map(verifier.describe).collect());
+        self.catch_up();
+        if let Some(ops) = ops {
+            if let Err(divergence) = self.compare(&*self.verifier.as_ref().unwrap().eq, ops) {
+                panic!("{}", divergence);
+            }
+        }
+
+        // all the readers have left!
+        // safety: we haven't freed the Box, and no readers are accessing the w_handle
+        let w_handle = unsafe { self.w_handle.as_mut() };
+
+        // safety: we will not swap while we hold this reference
+        let r_handle = unsafe {
+            self.r_handle
+                .inner
+                .ptr
+                .load(Ordering::Acquire)
+                .as_ref()
+                .unwrap()
+        };
+
+        // we cannot give owned operations to absorb_first
+        // since they'll also be needed by the r_handle copy
+        for op in self
+            .oplog
+            .iter_mut()
+            .map(|op| op.as_mut().expect("Nones are always temporary"))
+        {
+            T::absorb_first(w_handle, op, r_handle);
+        }
+        // the w_handle copy is about to become the r_handle, and can ignore the oplog
+        self.swap_index = self.oplog.len();
+
+        // w_handle (the old r_handle) is now fully up to date!
+    }
+
+    /// Bring the w_handle copy up to date with the r_handle copy, leaving only the operations
+    /// that neither has seen in the oplog.
+    ///
+    /// Must only be called once all readers have departed from the w_handle copy.
+    fn catch_up(&mut self) {
+        // all the readers have left!
+        // safety: we haven't freed the Box, and no readers are accessing the w_handle
+        let w_handle = unsafe { self.w_handle.as_mut() };
+
+        // safety: we will not swap while we hold this reference
+        let r_handle = unsafe {
+            self.r_handle
+                .inner
+                .ptr
+                .load(Ordering::Acquire)
+                .as_ref()
+                .unwrap()
+        };
+
+        if self.second {
+            Absorb::sync_with(w_handle, r_handle);
+            self.second = false
+        }
+
+        // the w_handle copy has not seen any of the writes in the oplog
+        // the r_handle copy has not seen any of the writes following swap_index
+        if self.swap_index != 0 {
+            // we can drain out the operations that only the w_handle copy needs
+            //
+            // NOTE: the if above is because drain(0..0) would remove 0
+            for op in self
+                .oplog
+                .drain(0..self.swap_index)
+                .map(|opt| opt.expect("Nones are always temporary"))
+            {
+                T::absorb_second(w_handle, op, r_handle);
+            }
+            self.swap_index = 0;
+        }
+    }
+
+    /// The operations [`catch_up`](Self::catch_up) would absorb into the w_handle copy.
+    fn catching_up(&self) -> impl Iterator<Item = &O> {
+        self.oplog.iter().take(self.swap_index).flatten()
+    }
+
+    /// Compare the two copies, which must both have absorbed the same operations by now.
+    fn compare(&self, eq: &dyn Fn(&T, &T) -> bool, ops: Vec<String>) -> Result<(), Divergence> {
+        // safety: no readers are accessing the w_handle, and readers never mutate the r_handle
+        let w_handle = unsafe { self.w_handle.as_ref() };
+        // safety: we will not swap while we hold this reference
+        let r_handle = unsafe {
+            self.r_handle
+                .inner
+                .ptr
+                .load(Ordering::Acquire)
+                .as_ref()
+                .unwrap()
+        };
+        if eq(w_handle, r_handle) {
+            Ok(())
+        } else {
+            Err(Divergence {
+                generation: self.r_handle.inner.generation.load(Ordering::Acquire),
+                ops,
+            })
+        }
+    }
+
     /// Publish as necessary to ensure that all operations are visible to readers.
     ///
     /// `WriteHandle::publish` will *always* wait for old readers to depart and swap the maps.
@@ -421,90 +783,651 @@ where
         self
     }
 
-    /// Returns a raw pointer to the write copy of the data (the one readers are _not_ accessing).
+    /// Append the given operation to the operational log, unless an operation with the same or
+    /// a higher sequence number has already been appended this way.
     ///
-    /// Note that it is only safe to mutate through this pointer if you _know_ that there are no
-    /// readers still present in this copy. This is not normally something you know; even after
-    /// calling `publish`, readers may still be in the write copy for some time. In general, the
-    /// only time you know this is okay is before the first call to `publish` (since no readers
-    /// ever entered the write copy).
-    // TODO: Make this return `Option<&mut T>`,
-    // and only `Some` if there are indeed to readers in the write copy.
-    pub fn raw_write_handle(&mut self) -> NonNull<T> {
-        self.w_handle
+    /// This makes it easy to apply each operation exactly once when they come from a source that
+    /// may deliver them more than once, like a message queue, as long as it assigns increasing
+    /// sequence numbers. The highest sequence number that has been appended is the high-water
+    /// mark, which readers see as of the last publish through
+    /// [`ReadHandle::high_water_mark`], and which is persisted and replicated along with the
+    /// operations (if those features are enabled). So after a restart, the source can pick up
+    /// right after it.
+    ///
+    /// The high-water mark stays put if operations are [compressed](Absorb::try_compress) away, or
+    /// a publish is [reverted](Self::revert_last_publish).
+    ///
+    /// Returns true if the operation was appended, and false if it was dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seq` is `u64::MAX`, which is reserved.
+    pub fn append_seq(&mut self, seq: u64, op: O) -> bool {
+        assert_ne!(
+            seq,
+            std::u64::MAX,
+            "u64::MAX is not a valid sequence number"
+        );
+        if self.appended_seq.map_or(false, |hwm| seq <= hwm) {
+            return false;
+        }
+        self.append(op);
+        self.appended_seq = Some(seq);
+        true
     }
 
-    /// Returns the backing data structure.
+    /// Returns the highest sequence number passed to [`append_seq`](Self::append_seq), including
+    /// those of operations that have not been published yet.
     ///
-    /// Makes sure that all the pending operations are applied and waits till all the read handles
-    /// have departed. Then it uses [`Absorb::drop_first`] to drop one of the copies of the data and
-    /// returns the other copy as a [`Taken`] smart pointer.
-    pub fn take(mut self) -> Taken<T, O> {
-        // It is always safe to `expect` here because `take_inner` is private
-        // and it is only called here and in the drop impl. Since we have an owned
-        // `self` we know the drop has not yet been called. And every first call of
-        // `take_inner` returns `Some`
-        self.take_inner()
-            .expect("inner is only taken here then self is dropped")
+    /// See [`ReadHandle::high_water_mark`] for the one readers see.
+    pub fn appended_high_water_mark(&self) -> Option<u64> {
+        self.appended_seq
     }
-}
 
-// allow using write handle for reads
-use std::ops::Deref;
-impl<T, O> Deref for WriteHandle<T, O>
-where
-    T: Absorb<O>,
-{
-    type Target = ReadHandle<T>;
-    fn deref(&self) -> &Self::Target {
-        &self.r_handle
+    /// Stop writing, but keep the data readable for all existing readers.
+    ///
+    /// Makes sure that all the pending operations are applied and waits till all readers have
+    /// departed from the write copy. Then it uses [`Absorb::drop_first`] to drop the write copy,
+    /// and leaves the read copy in place. Every existing [`ReadHandle`] and [`ReadHandleFactory`]
+    /// (as well as any handle they produce) can keep reading that copy, which will no longer
+    /// change. The copy is dropped using [`Absorb::drop_second`] once the last of them goes away.
+    ///
+    /// That includes the handles that threads keep for [`ReadHandleFactory::with_local`]. A thread
+    /// that used it holds on to the copy until it exits, or until it registers another
+    /// thread-local handle after every factory for this instance is gone, even if the thread never
+    /// reads again.
+    ///
+    /// If the instance is poisoned, pending operations are discarded, and readers stay locked out
+    /// until a new writer takes over.
+    ///
+    /// A new writer can take over a frozen instance through [`ReadHandleFactory::take_writer`].
+    ///
+    /// The data must be `Send`, since whichever thread the last reader is dropped on drops the
+    /// copy as well.
+    ///
+    /// [`ReadHandleFactory`]: crate::ReadHandleFactory
+    /// [`ReadHandleFactory::take_writer`]: crate::ReadHandleFactory::take_writer
+    /// [`ReadHandleFactory::with_local`]: crate::ReadHandleFactory::with_local
+    pub fn freeze(mut self)
+    where
+        T: Send,
+    {
+        self.publish_all();
+        self.leave_behind();
     }
-}
 
-impl<T, O> Extend<O> for WriteHandle<T, O>
-where
-    T: Absorb<O>,
-{
-    /// Add multiple operations to the operational log.
+    /// Choose what happens to the data if the `WriteHandle` is dropped while its thread is
+    /// panicking.
     ///
-    /// Their effects will not be exposed to readers until you call [`publish`](Self::publish)
-    fn extend<I>(&mut self, ops: I)
+    /// By default, the data is torn down just like on any other drop, and readers find out through
+    /// [`ReadHandle::was_dropped`]. With `freeze` set, the writer is [frozen](Self::freeze)
+    /// instead, without publishing the pending operations (which could easily panic again). The
+    /// readers keep the last published copy, and a new writer can take over through
+    /// [`ReadHandleFactory::take_writer`]. [`ReadHandle::was_dropped`] then stays false, so code
+    /// that watches for the writer to go away should check [`ReadHandle::is_frozen`] as well.
+    ///
+    /// This only applies to this `WriteHandle`; writers returned by `take_writer` start out with
+    /// the default.
+    ///
+    /// Like `freeze`, this requires the data to be `Send`.
+    ///
+    /// [`ReadHandleFactory::take_writer`]: crate::ReadHandleFactory::take_writer
+    pub fn freeze_on_panic(&mut self, freeze: bool) -> &mut Self
     where
-        I: IntoIterator<Item = O>,
+        T: Send,
     {
-        // During the first publish cycle, always use optimization.
-        if self.first {
-            // Safety: we know there are no outstanding w_handle readers, since we haven't
-            // refreshed ever before, so we can modify it directly!
-            let mut w_inner = self.raw_write_handle();
-            let w_inner = unsafe { w_inner.as_mut() };
-            let r_handle = self.enter().expect("map has not yet been destroyed");
-            // Because we are operating directly on the map, and nothing is aliased, we do want
-            // to perform drops, so we invoke absorb_second.
-            for op in ops {
-                Absorb::absorb_second(w_inner, op, &*r_handle);
-            }
-        } else if T::MAX_COMPRESS_RANGE == 0 {
-            // If compression is disabled, use efficient, non-compressing fallback.
-            self.oplog.extend(ops.into_iter().map(|op| Some(op)));
-        } else {
-            // Compression is enabled
-            // used to avoid walking more of the oplog than necessary.
-            let mut rev_dirty_range = 0usize..0;
-            for next in ops {
-                self.compress_insert_op(next, &mut rev_dirty_range);
-            }
-            self.oplog_retain_some(rev_dirty_range);
-        }
+        self.freeze_on_panic = freeze;
+        self
     }
-}
 
-impl<T: Absorb<O>, O> WriteHandle<T, O> {
-    /// Rev-iterate all ops appended since the last publish while attempting to combine them with the next op,
-    /// cut short when an attempt fails due to encountering a dependency (e.g. clear then set), or after running out of range.
-    fn compress_insert_op(&mut self, mut next: O, rev_dirty_range: &mut Range<usize>) {
-        // While debugging, make very very sure rev_dirty_range.start is correct.
-        debug_assert!(
+    /// Drop the write copy, and hand the read copy over to the readers.
+    fn leave_behind(&mut self) {
+        // Drop must not tear down the data after this.
+        self.taken = true;
+
+        // readers that were in the w_handle at the last publish must be gone before we drop it.
+        self.wait(&self.epochs);
+
+        // safety: w_handle was initially crated from a `Box`, and is no longer aliased.
+        Absorb::drop_first(unsafe { Box::from_raw(self.w_handle.as_ptr()) });
+
+        // the r_handle is now owned by the readers.
+        *self.r_handle.inner.drop_frozen.lock().unwrap() = Some(drop_frozen::<T, O>);
+        self.r_handle.inner.frozen.store(true, Ordering::Release);
+    }
+
+    /// Recover from a panic in one of the [`Absorb`] methods.
+    ///
+    /// If an `Absorb` method panics during [`publish`](Self::publish), the two copies of the data
+    /// may no longer mirror one another, and the instance is poisoned. The read copy still holds
+    /// the state as of the last successful `publish`, so this method waits for all readers to
+    /// depart from the write copy and then rebuilds it from the read copy using
+    /// [`Absorb::sync_with`]. All operations that were not yet visible to readers, including the
+    /// ones that caused the panic, are discarded, and [`append_seq`](Self::append_seq) accepts
+    /// their sequence numbers again so that they can be redelivered. Afterwards, readers can
+    /// [`enter`](ReadHandle::enter) again.
+    ///
+    /// Does nothing if the instance is not poisoned.
+    pub fn recover(&mut self) -> &mut Self {
+        if !self.is_poisoned() {
+            return self;
+        }
+
+        self.wait(&self.epochs);
+
+        // safety: we haven't freed the Box, and no readers are accessing the w_handle
+        let w_handle = unsafe { self.w_handle.as_mut() };
+        // safety: we will not swap while we hold this reference
+        let r_handle = unsafe {
+            self.r_handle
+                .inner
+                .ptr
+                .load(Ordering::Acquire)
+                .as_ref()
+                .unwrap()
+        };
+        Absorb::sync_with(w_handle, r_handle);
+
+        self.oplog.clear();
+        self.swap_index = 0;
+        self.appended_seq = self.published_seq;
+        if !self.first {
+            self.second = false;
+        }
+        self.r_handle.inner.poisoned.store(false, Ordering::Release);
+        self
+    }
+
+    /// Register a hook that is called with the operations that became visible to readers every
+    /// time [`publish`](Self::publish) is called.
+    ///
+    /// The hook is called right after readers have been pointed at the new copy of the data, with
+    /// exactly the operations that copy has, and the copy readers used until then has not, seen.
+    /// Hooks are called in the order they were registered, and are also called for the final
+    /// publishes done by [`take`](Self::take), [`freeze`](Self::freeze), and when the
+    /// `WriteHandle` is dropped.
+    ///
+    /// Returns an id that can be passed to [`remove_publish_hook`](Self::remove_publish_hook) to
+    /// stop calling the hook.
+    ///
+    /// # Panics
+    ///
+    /// Panics if nothing has been published yet. Operations appended before the first publish are
+    /// applied to the data directly, and never make it into a batch, so a hook registered then
+    /// would silently miss them. Call [`publish`](Self::publish) first.
+    ///
+    /// If the hook panics, the panic is propagated out of `publish`. The left-right instance
+    /// remains intact, but any hooks after it are not called for that batch.
+    ///
+    /// ```
+    /// use left_right::Absorb;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// # struct CounterAddOp(i32);
+    /// # impl Absorb<CounterAddOp> for i32 {
+    /// #     fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+    /// #         *self += operation.0;
+    /// #     }
+    /// #     fn sync_with(&mut self, first: &Self) {
+    /// #         *self = *first
+    /// #     }
+    /// # }
+    /// let (mut w, r) = left_right::new::<i32, CounterAddOp>();
+    /// w.publish();
+    ///
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    /// let hook_log = Arc::clone(&log);
+    /// let hook = w.on_publish(Box::new(move |batch| {
+    ///     let sum: i32 = batch.iter().map(|op| op.0).sum();
+    ///     hook_log.lock().unwrap().push((batch.generation(), sum));
+    /// }));
+    ///
+    /// w.append(CounterAddOp(1));
+    /// w.append(CounterAddOp(2));
+    /// w.publish();
+    /// assert_eq!(*log.lock().unwrap(), [(2, 3)]);
+    ///
+    /// assert!(w.remove_publish_hook(hook));
+    /// w.append(CounterAddOp(3)).publish();
+    /// assert_eq!(*log.lock().unwrap(), [(2, 3)]);
+    /// ```
+    pub fn on_publish(&mut self, hook: Box<dyn FnMut(Batch<'_, O>) + Send>) -> PublishHookId {
+        self.add_publish_hook(PublishHook::Always(hook))
+    }
+
+    /// Like [`on_publish`](Self::on_publish), except that the hook is dropped once it returns
+    /// false, so that hooks that feed something nobody listens to anymore do not pile up.
+    pub(crate) fn on_publish_while(
+        &mut self,
+        hook: Box<dyn FnMut(Batch<'_, O>) -> bool + Send>,
+    ) -> PublishHookId {
+        self.add_publish_hook(PublishHook::WhileWanted(hook))
+    }
+
+    fn add_publish_hook(&mut self, hook: PublishHook<O>) -> PublishHookId {
+        assert!(
+            !self.first,
+            "publish hooks can only be registered once something has been published"
+        );
+        let id = PublishHookId(self.next_hook_id);
+        self.next_hook_id += 1;
+        self.publish_hooks.push((id, hook));
+        id
+    }
+
+    /// Remove a hook registered with [`on_publish`](Self::on_publish), so that it is not called
+    /// for any later publish.
+    ///
+    /// Returns `false` if the hook had already been removed.
+    pub fn remove_publish_hook(&mut self, id: PublishHookId) -> bool {
+        let registered = self.publish_hooks.len();
+        self.publish_hooks.retain(|(hook, _)| *hook != id);
+        self.publish_hooks.len() != registered
+    }
+
+    /// Check that the two copies of the data are the same, according to `eq`.
+    ///
+    /// Since every operation is applied to each copy separately, an [`Absorb`] implementation
+    /// that is not deterministic, or whose [`absorb_second`](Absorb::absorb_second) does
+    /// something other than its `absorb_first`, makes the copies drift apart without anyone
+    /// noticing. This waits for readers to depart from the stale copy, brings it up to date with
+    /// the operations of the last publish (which the next publish would otherwise do), and then
+    /// compares it to the copy readers see.
+    ///
+    /// Returns the operations the stale copy absorbed if the copies differ. Nothing is compared
+    /// before the first publish, since there is only one copy with data in it until then.
+    ///
+    /// After this, [`revert_last_publish`](Self::revert_last_publish) has nothing to revert.
+    ///
+    /// ```
+    /// use left_right::Absorb;
+    ///
+    /// # struct CounterAddOp(i32);
+    /// # impl Absorb<CounterAddOp> for i32 {
+    /// #     fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+    /// #         *self += operation.0;
+    /// #     }
+    /// #     fn sync_with(&mut self, first: &Self) {
+    /// #         *self = *first
+    /// #     }
+    /// # }
+    /// # impl std::fmt::Debug for CounterAddOp {
+    /// #     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// #         write!(f, "CounterAddOp({})", self.0)
+    /// #     }
+    /// # }
+    /// let (mut w, r) = left_right::new::<i32, CounterAddOp>();
+    /// w.append(CounterAddOp(1)).publish();
+    /// w.append(CounterAddOp(2)).publish();
+    /// assert!(w.verify_consistency(|a, b| a == b).is_ok());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`publish`](Self::publish).
+    pub fn verify_consistency<F>(&mut self, eq: F) -> Result<(), Divergence>
+    where
+        F: Fn(&T, &T) -> bool,
+        O: fmt::Debug,
+    {
+        assert!(
+            !self.is_poisoned(),
+            "cannot verify a poisoned left-right instance; call `recover` first"
+        );
+        if self.first {
+            return Ok(());
+        }
+
+        let ops = self.catching_up().map(|op| format!("{:?}", op)).collect();
+        self.wait(&self.epochs);
+        let absorbed = panic::catch_unwind(AssertUnwindSafe(|| self.catch_up()));
+        if let Err(e) = absorbed {
+            self.r_handle.inner.poisoned.store(true, Ordering::Release);
+            panic::resume_unwind(e);
+        }
+        self.compare(&eq, ops)
+    }
+
+    /// Check that the two copies of the data are the same, according to `eq`, on every
+    /// [`publish`](Self::publish).
+    ///
+    /// The stale copy catches up with the operations of the previous publish at the start of
+    /// every publish, and this compares it to the copy readers see right after. So a publish
+    /// checks the operations of the one before it. See
+    /// [`verify_consistency`](Self::verify_consistency) for checking at any other time.
+    ///
+    /// This is meant for tests and debugging, since comparing entire copies is usually about as
+    /// expensive as it gets. Registering another `eq` replaces the previous one.
+    ///
+    /// # Panics
+    ///
+    /// If the copies differ, `publish` panics with the [`Divergence`] as the message, and the
+    /// left-right instance is poisoned.
+    pub fn verify_every_publish<F>(&mut self, eq: F) -> &mut Self
+    where
+        F: Fn(&T, &T) -> bool + Send + 'static,
+        O: fmt::Debug,
+    {
+        self.verifier = Some(Verifier {
+            eq: Box::new(eq),
+            describe: |op| format!("{:?}", op),
+        });
+        self
+    }
+
+    /// Keep the last `versions` published versions of the data around, so that readers can still
+    /// get at them with [`ReadHandle::enter_at`] after later publishes.
+    ///
+    /// Every publish then clones the copy it makes visible to readers (which also serves any
+    /// [requested snapshot](ReadHandle::request_snapshot)), and retained versions are dropped
+    /// once they are `versions` publishes old and no reader holds on to them anymore. So this is
+    /// mostly useful for data that is cheap to clone, such as persistent data structures.
+    ///
+    /// The version readers currently see is retained right away. Passing 0 stops retaining
+    /// versions, and discards the ones that were retained.
+    ///
+    /// Like snapshots, retained versions are shared with readers on other threads, so the data
+    /// must be `Send` and `Sync`.
+    ///
+    /// ```
+    /// use left_right::Absorb;
+    ///
+    /// # struct CounterAddOp(i32);
+    /// # impl Absorb<CounterAddOp> for i32 {
+    /// #     fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+    /// #         *self += operation.0;
+    /// #     }
+    /// #     fn sync_with(&mut self, first: &Self) {
+    /// #         *self = *first
+    /// #     }
+    /// # }
+    /// let (mut w, r) = left_right::new::<i32, CounterAddOp>();
+    /// w.retain_versions(2);
+    /// w.append(CounterAddOp(1)).publish();
+    /// let page = r.generation();
+    /// w.append(CounterAddOp(1)).publish();
+    /// assert_eq!(*r.enter().unwrap(), 2);
+    /// assert_eq!(*r.enter_at(page).unwrap(), 1);
+    /// ```
+    pub fn retain_versions(&mut self, versions: usize) -> &mut Self
+    where
+        T: Clone + Send + Sync,
+    {
+        let mut snapshots = self.r_handle.inner.snapshots();
+        match snapshots.retained {
+            _ if versions == 0 => snapshots.retained = None,
+            Some(ref mut retained) => {
+                retained.keep = versions;
+                while retained.versions.len() > versions {
+                    retained.versions.pop_front();
+                }
+            }
+            None => {
+                let mut retained = Retained {
+                    keep: versions,
+                    clone: T::clone,
+                    versions: VecDeque::with_capacity(versions),
+                };
+                // readers may never mutate the read copy, and we will not swap while we hold
+                // this guard, so this is the version of the current generation.
+                if let Some(current) = self.r_handle.enter() {
+                    let generation = self.r_handle.generation();
+                    retained.push(generation, std::sync::Arc::new(T::clone(&current)));
+                }
+                snapshots.retained = Some(retained);
+            }
+        }
+        drop(snapshots);
+        self
+    }
+
+    /// Add `ops` to the oplog without trying to compress them, as if compression was disabled.
+    #[cfg(feature = "testing")]
+    pub(crate) fn extend_uncompressed(&mut self, ops: Vec<O>) {
+        if self.first {
+            // the first publish cycle does not use the oplog at all.
+            self.extend(ops);
+        } else {
+            assert!(
+                !self.is_poisoned(),
+                "cannot append to a poisoned left-right instance; call `recover` first"
+            );
+            self.oplog.extend(ops.into_iter().map(Some));
+        }
+    }
+
+    /// Make the next publish expose `seq` as the high-water mark, and only accept later ones.
+    #[cfg(any(feature = "persist", feature = "replication"))]
+    pub(crate) fn resume_high_water_mark(&mut self, seq: Option<u64>) {
+        self.appended_seq = seq;
+    }
+
+    /// Make the next publish continue counting generations from `generation`.
+    #[cfg(any(feature = "persist", feature = "replication"))]
+    pub(crate) fn resume_generation(&mut self, generation: usize) {
+        self.r_handle
+            .inner
+            .generation
+            .store(generation, Ordering::Release);
+    }
+
+    /// Returns true if nothing has been appended or published yet.
+    #[cfg(feature = "persist")]
+    pub(crate) fn is_pristine(&self) -> bool {
+        self.first && !self.wrote_directly
+    }
+
+    /// Make every later publish panic once `failed` is set, without making anything visible.
+    #[cfg(feature = "persist")]
+    pub(crate) fn set_log_failed_flag(&mut self, failed: std::sync::Arc<AtomicBool>) {
+        self.log_failed = Some(failed);
+    }
+
+    /// Returns true if writing to the log failed, so that publishing again would leave a gap in it.
+    fn has_log_failed(&self) -> bool {
+        #[cfg(feature = "persist")]
+        {
+            if let Some(failed) = &self.log_failed {
+                return failed.load(Ordering::Acquire);
+            }
+        }
+        false
+    }
+
+    fn assert_log_intact(&self) {
+        assert!(
+            !self.has_log_failed(),
+            "cannot publish to a left-right instance whose log could not be written; rebuild it \
+             with `left_right::recover` instead"
+        );
+    }
+
+    /// Returns what is known about each [`ReadHandle`] currently registered with this left-right
+    /// instance, ordered by their slot index.
+    ///
+    /// This includes the handle the writer keeps for itself (which is what `WriteHandle`
+    /// dereferences to). Readers may come, go, enter and leave while this is collected, so the
+    /// result is only a best-effort picture.
+    pub fn readers(&self) -> Vec<ReaderInfo> {
+        self.epochs.readers().collect()
+    }
+
+    /// Returns a raw pointer to the write copy of the data (the one readers are _not_ accessing).
+    ///
+    /// Note that it is only safe to mutate through this pointer if you _know_ that there are no
+    /// readers still present in this copy. This is not normally something you know; even after
+    /// calling `publish`, readers may still be in the write copy for some time. In general, the
+    /// only time you know this is okay is before the first call to `publish` (since no readers
+    /// ever entered the write copy).
+    // TODO: Make this return `Option<&mut T>`,
+    // and only `Some` if there are indeed to readers in the write copy.
+    pub fn raw_write_handle(&mut self) -> NonNull<T> {
+        self.w_handle
+    }
+
+    /// Returns the backing data structure.
+    ///
+    /// Makes sure that all the pending operations are applied and waits till all the read handles
+    /// have departed. Then it uses [`Absorb::drop_first`] to drop one of the copies of the data and
+    /// returns the other copy as a [`Taken`] smart pointer.
+    ///
+    /// If the instance is poisoned, pending operations are discarded and the last successfully
+    /// published copy is returned.
+    pub fn take(mut self) -> Taken<T, O> {
+        // It is always safe to `expect` here because `take_inner` is private
+        // and it is only called here and in the drop impl. Since we have an owned
+        // `self` we know the drop has not yet been called. And every first call of
+        // `take_inner` returns `Some`
+        self.take_inner()
+            .expect("inner is only taken here then self is dropped")
+    }
+}
+
+// allow using write handle for reads
+use std::ops::Deref;
+impl<T, O> Deref for WriteHandle<T, O>
+where
+    T: Absorb<O>,
+{
+    type Target = ReadHandle<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.r_handle
+    }
+}
+
+impl<T, O> Extend<O> for WriteHandle<T, O>
+where
+    T: Absorb<O>,
+{
+    /// Add multiple operations to the operational log.
+    ///
+    /// Their effects will not be exposed to readers until you call [`publish`](Self::publish)
+    fn extend<I>(&mut self, ops: I)
+    where
+        I: IntoIterator<Item = O>,
+    {
+        assert!(
+            !self.is_poisoned(),
+            "cannot append to a poisoned left-right instance; call `recover` first"
+        );
+
+        // During the first publish cycle, always use optimization.
+        if self.first {
+            // Safety: we know there are no outstanding w_handle readers, since we haven't
+            // refreshed ever before, so we can modify it directly!
+            let mut w_inner = self.raw_write_handle();
+            let w_inner = unsafe { w_inner.as_mut() };
+            let r_handle = self.enter().expect("map has not yet been destroyed");
+            // Because we are operating directly on the map, and nothing is aliased, we do want
+            // to perform drops, so we invoke absorb_second.
+            let mut wrote = false;
+            let absorbed = panic::catch_unwind(AssertUnwindSafe(|| {
+                for op in ops {
+                    wrote = true;
+                    Absorb::absorb_second(w_inner, op, &*r_handle);
+                }
+            }));
+            drop(r_handle);
+            self.wrote_directly |= wrote;
+            if let Err(e) = absorbed {
+                self.r_handle.inner.poisoned.store(true, Ordering::Release);
+                panic::resume_unwind(e);
+            }
+        } else if T::MAX_COMPRESS_RANGE == 0 {
+            // If compression is disabled, use efficient, non-compressing fallback.
+            self.oplog.extend(ops.into_iter().map(|op| Some(op)));
+        } else {
+            // Compression is enabled
+            // used to avoid walking more of the oplog than necessary.
+            let mut rev_dirty_range = 0usize..0;
+            for next in ops {
+                self.compress_insert_op(next, &mut rev_dirty_range);
+            }
+            self.oplog_retain_some(rev_dirty_range);
+        }
+    }
+}
+
+impl<T, O> WriteHandle<T, O>
+where
+    T: Invertible<O>,
+{
+    /// Undo the operations of the last [`publish`](Self::publish), and publish that.
+    ///
+    /// The inverse of every operation that became visible with the last publish is computed
+    /// through [`Invertible::inverse`] from the state the data was in right before that
+    /// operation, and the inverses are then published, last one first. To readers, this is just
+    /// another publish, which also means that calling this again undoes the revert.
+    ///
+    /// Operations that have been appended but not yet published are not affected. They stay
+    /// pending, and are applied on top of the reverted state once published.
+    ///
+    /// Returns `false`, and does nothing, if the last publish made no operations visible. This is
+    /// also the case for the very first publish, which applies operations to the data directly,
+    /// and right after [`recover`](Self::recover).
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`publish`](Self::publish). If `inverse` panics, the left-right
+    /// instance is poisoned.
+    pub fn revert_last_publish(&mut self) -> bool {
+        assert!(
+            !self.is_poisoned(),
+            "cannot revert a poisoned left-right instance; call `recover` first"
+        );
+        self.assert_log_intact();
+        if self.swap_index == 0 {
+            return false;
+        }
+
+        // the w_handle copy is still in the state from right before the last publish, so bring
+        // it up to date one operation at a time, noting how to undo each as we go. this does the
+        // part of the next publish that only the w_handle copy needs early.
+        self.wait(&self.epochs);
+        let absorbed = panic::catch_unwind(AssertUnwindSafe(|| {
+            // safety: we haven't freed the Box, and no readers are accessing the w_handle
+            let w_handle = unsafe { self.w_handle.as_mut() };
+            // safety: we will not swap while we hold this reference
+            let r_handle = unsafe {
+                self.r_handle
+                    .inner
+                    .ptr
+                    .load(Ordering::Acquire)
+                    .as_ref()
+                    .unwrap()
+            };
+            debug_assert!(!self.second, "the first publish has no oplog to revert");
+
+            let mut inverses = VecDeque::with_capacity(self.swap_index);
+            for op in self.oplog.drain(0..self.swap_index).flatten() {
+                inverses.push_front(Some(w_handle.inverse(&op)));
+                T::absorb_second(w_handle, op, r_handle);
+            }
+            inverses
+        }));
+        let inverses = match absorbed {
+            Ok(inverses) => inverses,
+            Err(e) => {
+                self.r_handle.inner.poisoned.store(true, Ordering::Release);
+                panic::resume_unwind(e);
+            }
+        };
+        self.swap_index = 0;
+
+        // publish just the inverses, and then put back what was pending.
+        let pending = std::mem::replace(&mut self.oplog, inverses);
+        self.publish();
+        self.oplog.extend(pending);
+        true
+    }
+}
+
+impl<T: Absorb<O>, O> WriteHandle<T, O> {
+    /// Rev-iterate all ops appended since the last publish while attempting to combine them with the next op,
+    /// cut short when an attempt fails due to encountering a dependency (e.g. clear then set), or after running out of range.
+    fn compress_insert_op(&mut self, mut next: O, rev_dirty_range: &mut Range<usize>) {
+        // While debugging, make very very sure rev_dirty_range.start is correct.
+        debug_assert!(
             self.oplog
                 .iter()
                 .rev()
@@ -517,8 +1440,7 @@ impl<T: Absorb<O>, O> WriteHandle<T, O> {
             self.oplog
                 .iter()
                 .rev()
-                .skip(rev_dirty_range.start)
-                .next()
+                .nth(rev_dirty_range.start)
                 .map(Option::is_some)
                 .unwrap_or(true),
             "We start on the first Some if it exists."
@@ -738,21 +1660,20 @@ struct CheckWriteHandleSend;
 mod tests {
     use std::iter::once;
 
-    use crate::sync::{AtomicUsize, Mutex, Ordering};
+    use crate::sync::Ordering;
     use crate::{Absorb, TryCompressResult};
     use quickcheck_macros::quickcheck;
-    use slab::Slab;
     include!("./utilities.rs");
 
     #[test]
     fn append_test() {
         let (mut w, _r) = crate::new::<i32, _>();
-        assert_eq!(w.first, true);
+        assert!(w.first);
         w.append(CounterAddOp(1));
         assert_eq!(w.oplog.len(), 0);
-        assert_eq!(w.first, true);
+        assert!(w.first);
         w.publish();
-        assert_eq!(w.first, false);
+        assert!(!w.first);
         w.append(CounterAddOp(2));
         w.append(CounterAddOp(3));
         assert_eq!(w.oplog.len(), 2);
@@ -762,13 +1683,13 @@ mod tests {
         type Op = CompressibleCounterOp<{ usize::MAX }>;
         let (mut w, r) = crate::new::<i32, Op>();
         // Get first optimization out of the picture
-        assert_eq!(w.first, true);
+        assert!(w.first);
         w.append(Op::Add(8));
         assert_eq!(w.oplog.len(), 0);
-        assert_eq!(w.first, true);
+        assert!(w.first);
         w.publish();
         assert_eq!(*r.enter().unwrap(), 8);
-        assert_eq!(w.first, false);
+        assert!(!w.first);
         // Adds will combine
         w.append(Op::Add(7));
         w.append(Op::Add(6));
@@ -878,27 +1799,604 @@ mod tests {
         assert_eq!(*w.take(), 2);
     }
 
+    #[test]
+    fn poison_and_recover() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        type Op = PanickingCounterOp;
+        let (mut w, r) = crate::new::<i32, Op>();
+        w.append(Op::Add(1));
+        w.publish();
+        w.append(Op::Add(2));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 3);
+
+        // a panic half-way through absorbing poisons the instance
+        w.append(Op::Add(4));
+        w.append(Op::Panic);
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            w.publish();
+        }))
+        .is_err());
+        assert!(w.is_poisoned());
+        assert!(r.is_poisoned());
+        assert!(r.enter().is_none());
+        assert!(!r.was_dropped());
+
+        // both copies are rebuilt from the last published state
+        w.recover();
+        assert!(!r.is_poisoned());
+        assert_eq!(*r.enter().unwrap(), 3);
+        assert!(!w.has_pending_operations());
+        w.append(Op::Add(4));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 7);
+        w.append(Op::Add(1));
+        w.publish();
+        assert_eq!(*w.take(), 8);
+    }
+
+    #[test]
+    fn recover_accepts_discarded_seqs_again() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        type Op = PanickingCounterOp;
+        let (mut w, r) = crate::new::<i32, Op>();
+        w.publish();
+        assert!(w.append_seq(1, Op::Add(1)));
+        w.publish();
+
+        assert!(w.append_seq(2, Op::Add(2)));
+        assert!(w.append_seq(3, Op::Panic));
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            w.publish();
+        }))
+        .is_err());
+        w.recover();
+        assert_eq!(w.appended_high_water_mark(), Some(1));
+        assert_eq!(r.high_water_mark(), Some(1));
+
+        // the discarded operations can be redelivered
+        assert!(!w.append_seq(1, Op::Add(1)));
+        assert!(w.append_seq(2, Op::Add(2)));
+        assert!(w.append_seq(3, Op::Add(3)));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 6);
+        assert_eq!(r.high_water_mark(), Some(3));
+    }
+
+    #[test]
+    fn try_enter_errors() {
+        use crate::EnterError;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        type Op = PanickingCounterOp;
+        let (mut w, r) = crate::new::<i32, Op>();
+        w.append(Op::Add(1));
+        w.publish();
+        assert_eq!(*r.try_enter().unwrap(), 1);
+
+        w.append(Op::Panic);
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            w.publish();
+        }))
+        .is_err());
+        assert_eq!(r.try_enter().err(), Some(EnterError::Poisoned));
+
+        w.recover();
+        assert_eq!(*r.try_enter().unwrap(), 1);
+
+        drop(w);
+        assert_eq!(r.try_enter().err(), Some(EnterError::WriterDropped));
+        assert!(r.enter().is_none());
+    }
+
+    #[test]
+    fn poison_before_first_publish() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        type Op = PanickingCounterOp;
+        let (mut w, r) = crate::new_from_empty::<i32, Op>(1);
+        w.append(Op::Add(1));
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            w.append(Op::Panic);
+        }))
+        .is_err());
+        assert!(r.is_poisoned());
+        w.recover();
+        w.append(Op::Add(2));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 3);
+    }
+
+    #[test]
+    fn poisoned_take() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        type Op = PanickingCounterOp;
+        let (mut w, r) = crate::new::<i32, Op>();
+        w.append(Op::Add(1));
+        w.publish();
+        w.append(Op::Add(2));
+        w.append(Op::Panic);
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            w.publish();
+        }))
+        .is_err());
+        // the pending operations are discarded instead of being published again
+        assert_eq!(*w.take(), 1);
+        assert!(r.was_dropped());
+    }
+
+    #[test]
+    fn freeze_keeps_data_readable() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+        w.append(CounterAddOp(2));
+        let factory = r.factory();
+        w.freeze();
+
+        // pending operations were published, and the data outlives the writer
+        assert!(r.is_frozen());
+        assert!(!r.was_dropped());
+        assert_eq!(*r.enter().unwrap(), 3);
+        let r2 = factory.handle();
+        assert_eq!(*r2.enter().unwrap(), 3);
+        drop(r);
+        drop(factory);
+        assert_eq!(*r2.clone().enter().unwrap(), 3);
+    }
+
+    #[test]
+    fn freeze_drops_data_with_last_reader() {
+        // only held on to so we can observe when it is dropped
+        #[allow(dead_code)]
+        #[derive(Clone)]
+        struct Tracked(std::sync::Arc<()>);
+        impl Absorb<()> for Tracked {
+            fn absorb_first(&mut self, _: &mut (), _: &Self) {}
+            fn sync_with(&mut self, _: &Self) {}
+        }
+
+        let probe = std::sync::Arc::new(());
+        let (mut w, r) = crate::new_from_empty::<_, ()>(Tracked(std::sync::Arc::clone(&probe)));
+        w.append(());
+        w.publish();
+        assert_eq!(std::sync::Arc::strong_count(&probe), 3);
+        let factory = r.factory();
+        w.freeze();
+        // only the copy readers go through is left
+        assert_eq!(std::sync::Arc::strong_count(&probe), 2);
+        drop(r);
+        assert_eq!(std::sync::Arc::strong_count(&probe), 2);
+        drop(factory);
+        assert_eq!(std::sync::Arc::strong_count(&probe), 1);
+    }
+
+    #[test]
+    fn take_writer_after_freeze() {
+        let (mut w, r) = crate::new::<i32, _>();
+        let factory = r.factory();
+        assert!(factory.take_writer::<CounterAddOp>().is_none());
+        w.append(CounterAddOp(1));
+        w.publish();
+        w.freeze();
+
+        let mut w = factory.take_writer::<CounterAddOp>().unwrap();
+        // there is only ever one writer
+        assert!(factory.take_writer::<CounterAddOp>().is_none());
+        assert!(!r.is_frozen());
+        assert_eq!(*r.enter().unwrap(), 1);
+        w.append(CounterAddOp(2));
+        assert_eq!(*r.enter().unwrap(), 1);
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 3);
+        w.append(CounterAddOp(3));
+        w.publish();
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 6);
+
+        // a writer that is dropped normally still takes the data down with it
+        drop(w);
+        assert!(r.was_dropped());
+        assert!(factory.take_writer::<CounterAddOp>().is_none());
+    }
+
+    #[test]
+    fn take_writer_keeps_high_water_mark() {
+        let (mut w, r) = crate::new::<i32, CounterAddOp>();
+        let factory = r.factory();
+        assert!(w.append_seq(1, CounterAddOp(1)));
+        assert!(w.append_seq(2, CounterAddOp(2)));
+        w.publish();
+        // freezing publishes what is still pending
+        assert!(w.append_seq(3, CounterAddOp(3)));
+        w.freeze();
+        assert_eq!(r.high_water_mark(), Some(3));
+
+        let mut w = factory.take_writer::<CounterAddOp>().unwrap();
+        assert_eq!(w.appended_high_water_mark(), Some(3));
+        assert!(!w.append_seq(3, CounterAddOp(3)));
+        assert!(w.append_seq(4, CounterAddOp(4)));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 10);
+        assert_eq!(r.high_water_mark(), Some(4));
+    }
+
+    #[test]
+    fn take_writer_after_writer_thread_died() {
+        type Op = PanickingCounterOp;
+        let (mut w, r) = crate::new::<i32, Op>();
+        let factory = r.factory();
+        w.freeze_on_panic(true);
+        w.append(Op::Add(1));
+        w.publish();
+
+        // the writer thread dies with pending operations, and takes its writer with it
+        let jh = std::thread::spawn(move || {
+            w.append(Op::Add(2));
+            panic!("writer thread died");
+        });
+        assert!(jh.join().is_err());
+        assert!(r.is_frozen());
+        assert_eq!(*r.enter().unwrap(), 1);
+
+        // the writer thread dies because an operation panicked
+        let mut w = factory.take_writer::<Op>().unwrap();
+        w.freeze_on_panic(true);
+        w.append(Op::Add(2));
+        w.publish();
+        let jh = std::thread::spawn(move || {
+            w.append(Op::Panic);
+            w.publish();
+        });
+        assert!(jh.join().is_err());
+        assert!(r.is_frozen());
+        assert!(r.is_poisoned());
+        assert!(r.enter().is_none());
+
+        // a new writer picks up the last published copy
+        let mut w = factory.take_writer::<Op>().unwrap();
+        assert!(!r.is_poisoned());
+        assert_eq!(*r.enter().unwrap(), 3);
+        w.append(Op::Add(3));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 6);
+        assert_eq!(*w.take(), 6);
+    }
+
+    #[test]
+    fn writer_thread_died_without_freeze_on_panic() {
+        let (mut w, r) = crate::new::<i32, CounterAddOp>();
+        let factory = r.factory();
+        w.append(CounterAddOp(1));
+        w.publish();
+
+        let jh = std::thread::spawn(move || {
+            w.append(CounterAddOp(2));
+            panic!("writer thread died");
+        });
+        assert!(jh.join().is_err());
+        // readers see the writer go away, just like on any other drop
+        assert!(r.was_dropped());
+        assert!(!r.is_frozen());
+        assert!(r.enter().is_none());
+        assert!(factory.take_writer::<CounterAddOp>().is_none());
+    }
+
+    #[test]
+    fn snapshot_does_not_block_publish() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+        let snapshot = r.snapshot().unwrap();
+        // a guard would make the second publish hang
+        w.append(CounterAddOp(2));
+        w.publish();
+        w.append(CounterAddOp(3));
+        w.publish();
+        assert_eq!(*snapshot, 1);
+        assert_eq!(*r.enter().unwrap(), 6);
+
+        drop(w);
+        assert_eq!(r.snapshot().err(), Some(crate::EnterError::WriterDropped));
+        assert_eq!(*snapshot, 1);
+    }
+
+    #[test]
+    fn snapshot_shared_and_requested() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+
+        #[derive(Debug)]
+        struct CountClones(i32, Arc<AtomicUsize>);
+        impl Clone for CountClones {
+            fn clone(&self) -> Self {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                CountClones(self.0, Arc::clone(&self.1))
+            }
+        }
+        impl Absorb<CounterAddOp> for CountClones {
+            fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+                self.0 += operation.0;
+            }
+            fn sync_with(&mut self, first: &Self) {
+                self.0 = first.0;
+            }
+        }
+
+        let clones = Arc::new(AtomicUsize::new(0));
+        let (mut w, r) = crate::new_from_empty(CountClones(0, Arc::clone(&clones)));
+        clones.store(0, Ordering::SeqCst);
+        w.append(CounterAddOp(1));
+        w.publish();
+
+        // readers share the snapshot of a given version
+        let r2 = r.clone();
+        let a = r.snapshot().unwrap();
+        let b = r2.snapshot().unwrap();
+        assert!(crate::Snapshot::ptr_eq(&a, &b));
+        assert_eq!(a.0, 1);
+        assert_eq!(clones.load(Ordering::SeqCst), 1);
+
+        // but not across versions
+        w.append(CounterAddOp(2));
+        w.publish();
+        let c = r.snapshot().unwrap();
+        assert!(!crate::Snapshot::ptr_eq(&a, &c));
+        assert_eq!(c.0, 3);
+        assert_eq!(clones.load(Ordering::SeqCst), 2);
+
+        // a requested snapshot is cloned by the writer as part of publish
+        r.request_snapshot();
+        w.append(CounterAddOp(3));
+        w.publish();
+        assert_eq!(clones.load(Ordering::SeqCst), 3);
+        let guard = r.enter().unwrap();
+        let d = r2.snapshot().unwrap();
+        assert_eq!(clones.load(Ordering::SeqCst), 3);
+        assert_eq!(d.0, 6);
+        assert_eq!(guard.0, 6);
+        drop(guard);
+
+        // an unclaimed snapshot does not outlive the next publish
+        r.request_snapshot();
+        w.publish();
+        w.append(CounterAddOp(4));
+        w.publish();
+        assert_eq!(r.snapshot().unwrap().0, 10);
+        assert_eq!(clones.load(Ordering::SeqCst), 5);
+        assert_eq!(d.0, 6);
+    }
+
+    #[test]
+    fn snapshot_while_writer_clones() {
+        use std::sync::{mpsc, Mutex};
+        // the writer's clone says when it has started, and then waits until the reader has its
+        // snapshot.
+        static GATE: Mutex<Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>> = Mutex::new(None);
+
+        #[derive(Debug)]
+        struct Gated(i32);
+        impl Clone for Gated {
+            fn clone(&self) -> Self {
+                if std::thread::current().name() == Some("writer") {
+                    let gate = GATE.lock().unwrap();
+                    let (started, proceed) = gate.as_ref().unwrap();
+                    started.send(()).unwrap();
+                    proceed.recv().unwrap();
+                }
+                Gated(self.0)
+            }
+        }
+        impl Absorb<CounterAddOp> for Gated {
+            fn absorb_first(&mut self, operation: &mut CounterAddOp, _: &Self) {
+                self.0 += operation.0;
+            }
+            fn sync_with(&mut self, first: &Self) {
+                self.0 = first.0
+            }
+        }
+
+        let (mut w, r) = crate::new_from_empty::<Gated, CounterAddOp>(Gated(0));
+        w.append(CounterAddOp(1));
+        w.publish();
+        r.request_snapshot();
+        let (started_tx, started) = mpsc::channel();
+        let (proceed, proceed_rx) = mpsc::channel();
+        *GATE.lock().unwrap() = Some((started_tx, proceed_rx));
+        let writer = std::thread::Builder::new()
+            .name("writer".into())
+            .spawn(move || {
+                w.append(CounterAddOp(1));
+                w.publish();
+                w
+            })
+            .unwrap();
+
+        // the writer is busy cloning for the requested snapshot, which must not keep us from
+        // taking one of our own.
+        started.recv().unwrap();
+        assert_eq!(r.snapshot().unwrap().0, 1);
+        proceed.send(()).unwrap();
+        let _w = writer.join().unwrap();
+        assert_eq!(r.snapshot().unwrap().0, 2);
+    }
+
+    #[test]
+    fn revert_last_publish() {
+        #[derive(Debug)]
+        enum Op {
+            Set(i32),
+            Add(i32),
+        }
+        impl Absorb<Op> for i32 {
+            fn absorb_first(&mut self, operation: &mut Op, _: &Self) {
+                match *operation {
+                    Op::Set(v) => *self = v,
+                    Op::Add(v) => *self += v,
+                }
+            }
+            fn sync_with(&mut self, first: &Self) {
+                *self = *first
+            }
+        }
+        impl crate::Invertible<Op> for i32 {
+            fn inverse(&self, operation: &Op) -> Op {
+                match *operation {
+                    Op::Set(_) => Op::Set(*self),
+                    Op::Add(v) => Op::Add(-v),
+                }
+            }
+        }
+
+        let (mut w, r) = crate::new::<i32, Op>();
+        w.append(Op::Set(1));
+        // the first publish applies operations directly, so there is nothing to undo
+        w.publish();
+        assert!(!w.revert_last_publish());
+
+        w.append(Op::Add(2)).append(Op::Set(10)).append(Op::Add(5));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 15);
+        w.append(Op::Add(100));
+        assert!(w.revert_last_publish());
+        assert_eq!(*r.enter().unwrap(), 1);
+        assert_eq!(r.generation(), 3);
+
+        // pending operations survive, and apply on top of the reverted state
+        assert!(w.has_pending_operations());
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 101);
+
+        // reverting a revert redoes it
+        assert!(w.revert_last_publish());
+        assert_eq!(*r.enter().unwrap(), 1);
+        assert!(w.revert_last_publish());
+        assert_eq!(*r.enter().unwrap(), 101);
+
+        // an empty publish has nothing to undo
+        w.publish();
+        assert!(!w.revert_last_publish());
+        assert_eq!(*r.enter().unwrap(), 101);
+        w.append(Op::Set(0));
+        w.publish();
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 0);
+    }
+
+    #[test]
+    fn verify_consistency() {
+        #[derive(Debug)]
+        struct Add(i32);
+        impl Absorb<Add> for i32 {
+            fn absorb_first(&mut self, operation: &mut Add, _: &Self) {
+                *self += operation.0;
+            }
+            fn absorb_second(&mut self, operation: Add, _: &Self) {
+                // a bug that only shows up in one of the copies
+                *self += if operation.0 == 3 { 4 } else { operation.0 };
+            }
+            fn sync_with(&mut self, first: &Self) {
+                *self = *first
+            }
+        }
+
+        let (mut w, r) = crate::new::<i32, Add>();
+        // there is only one copy to speak of before the first publish
+        w.append(Add(1));
+        assert_eq!(w.verify_consistency(|a, b| a == b), Ok(()));
+        w.publish();
+        w.append(Add(2)).publish();
+        assert_eq!(w.verify_consistency(|a, b| a == b), Ok(()));
+        // checking again finds the copies the same
+        assert_eq!(w.verify_consistency(|a, b| a == b), Ok(()));
+
+        w.append(Add(3)).append(Add(1)).publish();
+        let divergence = w.verify_consistency(|a, b| a == b).unwrap_err();
+        assert_eq!(divergence.generation(), 3);
+        assert_eq!(divergence.ops(), ["Add(3)", "Add(1)"]);
+        assert_eq!(*r.enter().unwrap(), 7);
+
+        // the automatic check poisons the instance on the publish after the broken one
+        let (mut w, r) = crate::new::<i32, Add>();
+        w.verify_every_publish(|a, b| a == b);
+        w.append(Add(1)).publish();
+        w.append(Add(3)).publish();
+        assert_eq!(*r.enter().unwrap(), 4);
+        let divergence = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            w.publish();
+        }))
+        .unwrap_err();
+        assert_eq!(
+            divergence.downcast_ref::<String>().unwrap(),
+            "the two copies differ after catching up to generation 2 with operations [Add(3)]"
+        );
+        assert!(r.is_poisoned());
+        w.recover();
+        w.append(Add(1)).publish();
+        assert_eq!(*r.enter().unwrap(), 5);
+    }
+
+    #[test]
+    fn retain_versions() {
+        let (mut w, r) = crate::new::<i32, _>();
+        assert_eq!(r.enter_at(0).unwrap_err().oldest_retained(), None);
+
+        w.append(CounterAddOp(1));
+        w.publish();
+        w.retain_versions(3);
+        // the current version is retained right away
+        assert_eq!(*r.enter_at(1).unwrap(), 1);
+
+        for i in 2..=4 {
+            w.append(CounterAddOp(i));
+            w.publish();
+        }
+        assert_eq!(r.generation(), 4);
+        let old = r.enter_at(2).unwrap();
+        assert_eq!(*old, 3);
+        assert_eq!(*r.enter_at(4).unwrap(), 10);
+        assert_eq!(
+            r.enter_at(1).unwrap_err(),
+            crate::VersionGone { oldest: Some(2) }
+        );
+        assert!(r.enter_at(5).is_err());
+
+        // retained versions double as snapshots
+        assert!(crate::Snapshot::ptr_eq(
+            &r.snapshot().unwrap(),
+            &r.enter_at(4).unwrap()
+        ));
+
+        // shrinking drops the oldest versions, but not the ones readers hold on to
+        w.retain_versions(1);
+        assert_eq!(r.enter_at(3).unwrap_err().oldest_retained(), Some(4));
+        assert_eq!(*old, 3);
+
+        w.retain_versions(0);
+        w.append(CounterAddOp(5));
+        w.publish();
+        assert_eq!(r.enter_at(5).unwrap_err().oldest_retained(), None);
+    }
+
     #[test]
     fn wait_test() {
+        use crate::epochs::EpochRegistry;
         use std::sync::{Arc, Barrier};
         use std::thread;
         let (mut w, _r) = crate::new::<i32, CounterAddOp>();
 
         // Case 1: If epoch is set to default.
         let test_epochs: crate::Epochs = Default::default();
-        let mut test_epochs = test_epochs.lock().unwrap();
         // since there is no epoch to waiting for, wait function will return immediately.
-        w.wait(&mut test_epochs);
+        w.wait(&test_epochs);
 
         // Case 2: If one of the reader is still reading(epoch is odd and count is same as in last_epoch)
         // and wait has been called.
-        let held_epoch = Arc::new(AtomicUsize::new(1));
-
+        let registrations: Vec<_> = (0..3)
+            .map(|_| EpochRegistry::register(&test_epochs))
+            .collect();
+        registrations[0].epoch().store(2, Ordering::SeqCst);
+        registrations[1].epoch().store(2, Ordering::SeqCst);
+        registrations[2].epoch().store(1, Ordering::SeqCst);
         w.last_epochs = vec![2, 2, 1];
-        let mut epochs_slab = Slab::new();
-        epochs_slab.insert(Arc::new(AtomicUsize::new(2)));
-        epochs_slab.insert(Arc::new(AtomicUsize::new(2)));
-        epochs_slab.insert(Arc::clone(&held_epoch));
 
         let barrier = Arc::new(Barrier::new(2));
 
@@ -906,14 +2404,13 @@ mod tests {
 
         // check writers waiting state before calling wait.
         let is_waiting_v = is_waiting.load(Ordering::Relaxed);
-        assert_eq!(false, is_waiting_v);
+        assert!(!is_waiting_v);
 
         let barrier2 = Arc::clone(&barrier);
-        let test_epochs = Arc::new(Mutex::new(epochs_slab));
+        let wait_epochs = Arc::clone(&test_epochs);
         let wait_handle = thread::spawn(move || {
             barrier2.wait();
-            let mut test_epochs = test_epochs.lock().unwrap();
-            w.wait(&mut test_epochs);
+            w.wait(&wait_epochs);
         });
 
         barrier.wait();
@@ -923,13 +2420,260 @@ mod tests {
             thread::yield_now();
         }
 
-        held_epoch.fetch_add(1, Ordering::SeqCst);
+        registrations[2].epoch().fetch_add(1, Ordering::SeqCst);
 
         // join to make sure that wait must return after the progress/increment
         // of held_epoch.
         let _ = wait_handle.join();
     }
 
+    #[test]
+    fn register_during_publish() {
+        use std::sync::mpsc;
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+        w.append(CounterAddOp(1));
+        w.publish();
+
+        // hold up the publish after the next one, which has to wait for this reader to leave
+        let guard = r.enter().unwrap();
+        w.publish();
+        let factory = r.factory();
+        let is_waiting = std::sync::Arc::clone(&w.is_waiting);
+        let (tx, rx) = mpsc::channel();
+        let publisher = std::thread::spawn(move || {
+            w.append(CounterAddOp(1));
+            w.publish();
+            tx.send(()).unwrap();
+            w
+        });
+        while !is_waiting.load(Ordering::Relaxed) {
+            std::thread::yield_now();
+        }
+
+        // readers can come and go while the writer is waiting
+        let handles: Vec<_> = (0..100).map(|_| factory.handle()).collect();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(*handles[99].enter().unwrap(), 2);
+        drop(handles);
+        assert!(rx.try_recv().is_err());
+
+        drop(guard);
+        let _w = publisher.join().unwrap();
+        assert_eq!(*r.enter().unwrap(), 3);
+    }
+
+    #[test]
+    fn reader_introspection() {
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+        let factory = r.factory();
+        assert_eq!(factory.reader_count(), 2);
+
+        let r2 = factory.handle();
+        let guard = r.enter().unwrap();
+        let again = r.enter().unwrap();
+        drop(r2.enter());
+        assert_eq!(r.enter_count(), 2);
+        assert_eq!(r2.enter_count(), 1);
+
+        let readers = w.readers();
+        assert_eq!(readers.len(), 3);
+        assert_eq!(factory.reader_count(), 3);
+        // the writer's own handle comes after the one it was cloned from, and has entered once to
+        // apply the first operation directly.
+        assert_eq!(
+            readers
+                .iter()
+                .map(|r| (r.index, r.active, r.enters))
+                .collect::<Vec<_>>(),
+            vec![(0, true, 2), (1, false, 1), (2, false, 1)]
+        );
+
+        drop((guard, again));
+        drop(r2);
+        assert!(w.readers().iter().all(|r| !r.active));
+        assert_eq!(factory.reader_count(), 2);
+
+        // a handle that reuses a slot starts counting from scratch
+        let r3 = factory.handle();
+        assert_eq!(r3.enter_count(), 0);
+        assert_eq!(w.readers()[2].index, 2);
+        assert_eq!(w.readers()[2].enters, 0);
+    }
+
+    #[test]
+    fn publish_hooks() {
+        use std::sync::{Arc, Mutex};
+        let (mut w, r) = crate::new::<i32, _>();
+        w.append(CounterAddOp(1));
+        w.publish();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = Arc::clone(&seen);
+        w.on_publish(Box::new(move |batch| {
+            let ops = batch.iter().map(|op| op.0).collect::<Vec<_>>();
+            assert_eq!(batch.len(), ops.len());
+            hook_seen.lock().unwrap().push((batch.generation(), ops));
+        }));
+        assert_eq!(*r.enter().unwrap(), 1);
+        assert!(seen.lock().unwrap().is_empty());
+
+        w.append(CounterAddOp(2));
+        w.append(CounterAddOp(3));
+        w.publish();
+        w.publish();
+        w.append(CounterAddOp(4));
+        assert_eq!(*seen.lock().unwrap(), vec![(2, vec![2, 3]), (3, vec![])]);
+
+        // the final publishes done by take are observed too
+        let taken = w.take();
+        assert_eq!(*taken, 10);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(2, vec![2, 3]), (3, vec![]), (4, vec![4]), (5, vec![])]
+        );
+    }
+
+    #[test]
+    fn publish_hooks_see_compressed_ops() {
+        use std::sync::{Arc, Mutex};
+        type Op = CompressibleCounterOp<{ usize::MAX }>;
+        let (mut w, _r) = crate::new::<i32, Op>();
+        w.publish();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = Arc::clone(&seen);
+        w.on_publish(Box::new(move |batch| {
+            hook_seen
+                .lock()
+                .unwrap()
+                .extend(batch.into_iter().map(|op| format!("{:?}", op)));
+        }));
+
+        w.extend([Op::Add(1), Op::Add(2), Op::Sub(1), Op::Set(5), Op::Add(1)]);
+        w.publish();
+        assert_eq!(*seen.lock().unwrap(), ["Set(5)", "Add(1)"]);
+        drop(w);
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "publish hooks can only be registered once something has been published"
+    )]
+    fn publish_hook_before_first_publish() {
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        w.append(CounterAddOp(1));
+        w.on_publish(Box::new(|_| {}));
+    }
+
+    #[test]
+    fn remove_publish_hooks() {
+        use std::sync::{Arc, Mutex};
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        w.publish();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = Arc::clone(&seen);
+        let first = w.on_publish(Box::new(move |batch| {
+            hook_seen
+                .lock()
+                .unwrap()
+                .push(("first", batch.generation()));
+        }));
+        let hook_seen = Arc::clone(&seen);
+        let second = w.on_publish(Box::new(move |batch| {
+            hook_seen
+                .lock()
+                .unwrap()
+                .push(("second", batch.generation()));
+        }));
+        assert_ne!(first, second);
+
+        w.publish();
+        assert!(w.remove_publish_hook(first));
+        assert!(!w.remove_publish_hook(first));
+        w.publish();
+        assert!(w.remove_publish_hook(second));
+        w.publish();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [("first", 2), ("second", 2), ("second", 3)]
+        );
+    }
+
+    #[test]
+    fn unwanted_publish_hooks_are_dropped() {
+        let (mut w, _r) = crate::new::<i32, CounterAddOp>();
+        w.publish();
+
+        let mut calls = 0;
+        w.on_publish_while(Box::new(move |_| {
+            calls += 1;
+            calls < 2
+        }));
+        let kept = w.on_publish(Box::new(|_| {}));
+        assert_eq!(w.publish_hooks.len(), 2);
+        w.publish();
+        assert_eq!(w.publish_hooks.len(), 2);
+        w.publish();
+        // the other hook stays
+        assert_eq!(w.publish_hooks.len(), 1);
+        assert_eq!(w.publish_hooks[0].0, kept);
+    }
+
+    #[test]
+    fn append_seq_with_compression() {
+        use std::sync::{Arc, Mutex};
+        type Op = CompressibleCounterOp<{ usize::MAX }>;
+        let (mut w, r) = crate::new::<i32, Op>();
+        assert!(w.append_seq(1, Op::Add(1)));
+        assert!(!w.append_seq(1, Op::Add(1)));
+        assert_eq!(r.high_water_mark(), None);
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 1);
+        assert_eq!(r.high_water_mark(), Some(1));
+
+        let marks = Arc::new(Mutex::new(Vec::new()));
+        let hook_marks = Arc::clone(&marks);
+        w.on_publish(Box::new(move |batch| {
+            hook_marks
+                .lock()
+                .unwrap()
+                .push((batch.len(), batch.high_water_mark()));
+        }));
+
+        // the ops of 3 and 4 are merged into that of 2, and then all of them into that of 5
+        assert!(w.append_seq(2, Op::Add(2)));
+        assert!(w.append_seq(3, Op::Add(3)));
+        assert!(!w.append_seq(2, Op::Add(2)));
+        assert!(w.append_seq(4, Op::Add(4)));
+        assert_eq!(w.oplog.len(), 1);
+        assert!(w.append_seq(5, Op::Set(10)));
+        assert!(!w.append_seq(5, Op::Set(10)));
+        assert_eq!(w.oplog.len(), 1);
+        assert_eq!(w.appended_high_water_mark(), Some(5));
+        assert_eq!(r.high_water_mark(), Some(1));
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 10);
+        assert_eq!(r.high_water_mark(), Some(5));
+
+        // gaps are fine, and plain appends leave the mark alone
+        assert!(w.append_seq(8, Op::Add(1)));
+        w.append(Op::Add(1));
+        assert!(!w.append_seq(7, Op::Add(1)));
+        w.publish();
+        w.publish();
+        assert_eq!(*r.enter().unwrap(), 12);
+        assert_eq!(r.high_water_mark(), Some(8));
+        assert_eq!(
+            *marks.lock().unwrap(),
+            [(1, Some(5)), (1, Some(8)), (0, Some(8))]
+        );
+    }
+
     #[test]
     fn flush_noblock() {
         let (mut w, r) = crate::new::<i32, _>();
@@ -1035,7 +2779,7 @@ mod tests {
         // Get first optimization out of the picture
         w.publish();
         assert_eq!(*r.enter().unwrap(), 0);
-        assert_eq!(w.first, false);
+        assert!(!w.first);
         // Both Adds will combine
         w.append(Op::Add(7));
         w.append(Op::Add(6));
@@ -1066,7 +2810,7 @@ mod tests {
         let (mut w, _r) = crate::new::<i32, Op>();
         // Get first optimization out of the picture
         w.publish();
-        assert_eq!(w.first, false);
+        assert!(!w.first);
         // Force contrived oplog, causes Sub of second extend to remove the first Sub during compression,
         // bridging the gap between Nones, which rev_dirty_range.start is able to exploit.
         w.oplog.extend([
@@ -1097,7 +2841,7 @@ mod tests {
         let (mut w, _r) = crate::new::<i32, Op>();
         // Get first optimization out of the picture
         w.publish();
-        assert_eq!(w.first, false);
+        assert!(!w.first);
         // Force contrived oplog which causes none removal to stop early after failing to find a Some to swap a None with.
         w.oplog
             .extend([Some(Op::Add(3)), Some(Op::Sub(1)), None, Some(Op::Sub(1))]);
@@ -1114,7 +2858,7 @@ mod tests {
         let (mut w, _) = crate::new::<i32, Op>();
         // Get non-compressing first optimization out of the picture
         w.publish();
-        assert_eq!(w.first, false);
+        assert!(!w.first);
 
         // Map numbers to Ops, insert and publish them
         let mut remaining = input.0.len();
@@ -1144,7 +2888,7 @@ mod tests {
                 w.extend(chunk);
             } else {
                 // Occasionally not compressing covers more corner cases
-                w.oplog.extend(chunk.map(|op| Some(op)));
+                w.oplog.extend(chunk.map(Some));
             }
             if publish {
                 w.publish();
@@ -1161,7 +2905,7 @@ mod tests {
         let (mut w, _) = crate::new::<i32, Op>();
         // Get non-compressing first optimization out of the picture
         w.publish();
-        assert_eq!(w.first, false);
+        assert!(!w.first);
         w.oplog.extend([
             Some(Op::Sub(1)),
             Some(Op::Add(1)),
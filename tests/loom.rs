@@ -25,4 +25,166 @@ mod loom_tests {
             assert_eq!(1, val);
         });
     }
+
+    #[test]
+    fn register_during_publish() {
+        loom::model(|| {
+            let (mut w, r) = left_right::new::<i32, _>();
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            // the writer and `r` fill up the first segment of reader slots, so this also
+            // covers appending a segment while the writer scans the existing ones.
+            let factory = r.factory();
+            let jh = thread::spawn(move || *factory.handle().enter().unwrap());
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            let val = jh.join().unwrap();
+            assert!(val == 1 || val == 2);
+            assert_eq!(*r.enter().unwrap(), 2);
+        });
+    }
+
+    #[test]
+    fn reregister_during_publish() {
+        loom::model(|| {
+            let (mut w, r) = left_right::new::<i32, _>();
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            let jh = thread::spawn(move || {
+                let first = *r.enter().unwrap();
+                // give up our slot, and most likely claim it again right away.
+                let factory = r.factory();
+                drop(r);
+                let r = factory.handle();
+                let second = *r.enter().unwrap();
+                assert!(second >= first);
+            });
+
+            // NOTE: a second publish here would make loom report readers in the copy the writer
+            // is absorbing into, since our loom shim cannot model the `SeqCst` fences that rule
+            // that out (see `sync::fence`).
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            jh.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn take_during_read() {
+        loom::model(|| {
+            let (mut w, r) = left_right::new::<i32, _>();
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            // nothing is pending, so `take` does not publish again before it swaps in null.
+            let jh = thread::spawn(move || r.enter().map(|guard| *guard));
+
+            let taken = w.take();
+            assert_eq!(*taken, 1);
+
+            let val = jh.join().unwrap();
+            assert!(val == Some(1) || val.is_none());
+        });
+    }
+
+    #[test]
+    fn factory_readers() {
+        // two readers and a writer make for a lot of interleavings, so bound how often threads
+        // can be preempted to keep this tractable.
+        let mut model = loom::model::Builder::new();
+        model.preemption_bound = Some(3);
+        model.check(|| {
+            let (mut w, r) = left_right::new::<i32, _>();
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            // the factory is shared between threads, and each creates its own reader from it.
+            let factory = r.factory();
+            let jhs: Vec<_> = (0..2)
+                .map(|_| {
+                    let factory = factory.clone();
+                    thread::spawn(move || {
+                        let r = factory.handle();
+                        let first = *r.enter().unwrap();
+                        let second = *r.enter().unwrap();
+                        assert!(second >= first);
+                        first
+                    })
+                })
+                .collect();
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            for jh in jhs {
+                let val = jh.join().unwrap();
+                assert!(val == 1 || val == 2);
+            }
+            assert_eq!(*factory.handle().enter().unwrap(), 2);
+        });
+    }
+
+    #[test]
+    fn reentrant_enter() {
+        loom::model(|| {
+            let (mut w, r) = left_right::new::<i32, _>();
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            let jh = thread::spawn(move || {
+                let outer = r.enter().unwrap();
+                // a nested enter does not touch the epoch, and may already see the copy the
+                // writer swapped in since. both copies must stay put until the last guard is gone.
+                let inner = r.enter().unwrap();
+                assert!(*inner >= *outer);
+                drop(outer);
+                let val = *inner;
+                drop(inner);
+                assert!(*r.enter().unwrap() >= val);
+                val
+            });
+
+            w.append(CounterAddOp(1));
+            w.publish();
+
+            let val = jh.join().unwrap();
+            assert!(val == 1 || val == 2);
+        });
+    }
+
+    #[test]
+    fn compress_during_read() {
+        type Op = CompressibleCounterOp<2>;
+
+        loom::model(|| {
+            let (mut w, r) = left_right::new::<i32, Op>();
+
+            // get past the first publish, which never compresses.
+            w.publish();
+
+            let jh = thread::spawn(move || {
+                let val = *r.enter().unwrap();
+                assert!(val == 0 || val == 4);
+                val
+            });
+
+            // the `Set` makes the earlier operations redundant, so they are compressed away
+            // while the reader is around.
+            w.extend([Op::Add(1), Op::Sub(2), Op::Add(3), Op::Set(3), Op::Add(1)]);
+            w.publish();
+
+            jh.join().unwrap();
+            assert_eq!(*w.enter().unwrap(), 4);
+        });
+    }
 }